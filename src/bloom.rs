@@ -0,0 +1,92 @@
+//! Bloom filter over a dictionary's keys, so most misses are answered
+//! without decompressing anything.
+
+use crate::mphf::hash64;
+
+/// Hash seed, so the filter's bits don't correlate with the MPHF's buckets
+const SEED: u64 = 0x9e3779b97f4a7c15;
+
+pub struct BloomFilter {
+    /// Number of bits set per key
+    k: u8,
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    /// ~1% false positives at 10 bits per key, ~0.1% at 15
+    pub fn build(keys: &[&str], bits_per_key: u32) -> Self {
+        let num_bits = (keys.len() as u64 * bits_per_key as u64).max(64);
+        let k = ((bits_per_key as f64 * std::f64::consts::LN_2).round() as u8).clamp(1, 30);
+        let mut filter = BloomFilter {
+            k,
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+        };
+        for key in keys {
+            for bit in filter.bit_positions(key) {
+                filter.bits[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        filter
+    }
+
+    /// Double hashing: bit i is `h1 + i * h2`
+    fn bit_positions(&self, key: &str) -> impl Iterator<Item = usize> + use<> {
+        let h = hash64(key.as_bytes(), SEED);
+        let h2 = h.rotate_left(32) | 1;
+        let num_bits = self.bits.len() as u64 * 64;
+        (0..self.k as u64).map(move |i| (h.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+
+    /// Size of the bit array
+    pub fn num_bits(&self) -> u64 {
+        self.bits.len() as u64 * 64
+    }
+
+    /// False if `key` was definitely not built in
+    pub fn may_contain(&self, key: &str) -> bool {
+        self.bit_positions(key)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// k (1 B) | bits (8 B LE words)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut b = Vec::with_capacity(1 + self.bits.len() * 8);
+        b.push(self.k);
+        for word in &self.bits {
+            b.extend(word.to_le_bytes());
+        }
+        b
+    }
+
+    pub fn from_bytes(b: &[u8]) -> Result<Self, String> {
+        match b.split_first() {
+            Some((&k, bits)) if k > 0 && !bits.is_empty() && bits.len() % 8 == 0 => {
+                Ok(BloomFilter {
+                    k,
+                    bits: bits
+                        .chunks_exact(8)
+                        .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+                        .collect(),
+                })
+            }
+            _ => Err("Malformed bloom filter section".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_false_positives() {
+        let words: Vec<String> = (0..200).map(|i| format!("perro{}", i)).collect();
+        let keys: Vec<&str> = words.iter().map(|w| w.as_str()).collect();
+        let bloom = BloomFilter::build(&keys, 10);
+        assert!(keys.iter().all(|k| bloom.may_contain(k)));
+        let false_positives = (0..10_000)
+            .filter(|i| bloom.may_contain(&format!("gato{}", i)))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+}
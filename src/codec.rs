@@ -0,0 +1,550 @@
+//! Compression of the stream after the section directory, see [`Codec`].
+//! Every codec gives random access to the decompressed stream.
+
+use crate::{Codec, FrameSize, RegionCompression};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use zeekstd::{EncodeOptions, FrameSizePolicy, SeekTable};
+
+/// Compresses `data` as a single frame (block with LZ4), followed by the
+/// stream's trailer
+pub fn compress_frame(codec: Codec, data: &[u8], level: i32) -> Vec<u8> {
+    let mut encoder = StreamEncoder::new(codec, Vec::with_capacity(data.len() / 2));
+    encoder.frame(data, level).expect("writing to a Vec");
+    encoder.finish().expect("writing to a Vec")
+}
+
+/// Input is fed to the compressors in chunks of this size, whatever it's
+/// read from, so the output only depends on the data
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Compresses regions back to back into a single stream, reading each
+/// region and writing the stream as it goes, so neither has to fit in
+/// memory. With zstd and LZ4, each region starts on a new frame (block) and
+/// uses its own frame size; LZ4 has no levels and cuts blocks by
+/// uncompressed size only.
+pub struct StreamEncoder<W: Write> {
+    codec: Codec,
+    out: Counting<W>,
+    /// zstd's frames so far
+    seek_table: SeekTable,
+    /// LZ4's blocks so far: (compressed size, decompressed size) per block
+    lz4_table: Vec<u8>,
+    lz4_blocks: u32,
+}
+
+impl<W: Write> StreamEncoder<W> {
+    pub fn new(codec: Codec, out: W) -> Self {
+        StreamEncoder {
+            codec,
+            out: Counting {
+                inner: out,
+                count: 0,
+            },
+            seek_table: SeekTable::new(),
+            lz4_table: Vec::new(),
+            lz4_blocks: 0,
+        }
+    }
+
+    /// Compresses everything `data` reads as the next region. Frames (LZ4
+    /// blocks) sized by uncompressed size end at content-defined points, see
+    /// [`FrameCutter`]. Returns the region's compressed size.
+    pub fn region<R: Read>(&mut self, data: R, compression: RegionCompression) -> io::Result<u64> {
+        let cutter = match compression.frame_size {
+            FrameSize::Uncompressed(size) => Some(FrameCutter::new(size)),
+            FrameSize::Compressed(_) if self.codec == Codec::Lz4 => {
+                Some(FrameCutter::new(frame_size(compression.frame_size)))
+            }
+            FrameSize::Compressed(_) => None,
+        };
+        self.compress(data, compression, cutter)
+    }
+
+    /// Compresses `data` as the next region, in a single frame (LZ4 block)
+    /// whatever its size. Returns its compressed size.
+    pub fn frame(&mut self, data: &[u8], level: i32) -> io::Result<u64> {
+        let size = data.len().clamp(1, u32::MAX as usize) as u32;
+        let compression = RegionCompression {
+            level,
+            frame_size: FrameSize::Uncompressed(size),
+        };
+        self.compress(data, compression, None)
+    }
+
+    fn compress<R: Read>(
+        &mut self,
+        mut data: R,
+        compression: RegionCompression,
+        mut cutter: Option<FrameCutter>,
+    ) -> io::Result<u64> {
+        let start = self.out.count;
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        match self.codec {
+            Codec::Zstd => {
+                let mut n = read_full(&mut data, &mut chunk)?;
+                // Empty regions get no frame
+                if n == 0 {
+                    return Ok(0);
+                }
+                let frame_size_policy = match compression.frame_size {
+                    FrameSize::Uncompressed(size) => FrameSizePolicy::Uncompressed(size),
+                    FrameSize::Compressed(size) => FrameSizePolicy::Compressed(size),
+                };
+                let opts = EncodeOptions::new()
+                    .checksum_flag(false)
+                    .compression_level(compression.level)
+                    .frame_size_policy(frame_size_policy);
+                let mut encoder =
+                    zeekstd::Encoder::with_opts(&mut self.out, opts).map_err(io::Error::other)?;
+                // The cutter ends frames before the encoder would, at the
+                // latest once they reach the frame size
+                let mut frame_len = 0;
+                while n > 0 {
+                    let mut rest = &chunk[..n];
+                    while let Some(cut) = cutter.as_mut().and_then(|c| c.next_cut(rest)) {
+                        encoder.write_all(&rest[..cut])?;
+                        encoder.end_frame().map_err(io::Error::other)?;
+                        rest = &rest[cut..];
+                        frame_len = 0;
+                    }
+                    encoder.write_all(rest)?;
+                    frame_len += rest.len();
+                    n = read_full(&mut data, &mut chunk)?;
+                }
+                if frame_len > 0 {
+                    encoder.end_frame().map_err(io::Error::other)?;
+                }
+                encoder.flush()?;
+
+                // Frames are appended to the output in order, so the
+                // region's seek table just continues the previous ones
+                let region_table = encoder.into_seek_table();
+                for i in 0..region_table.num_frames() {
+                    self.seek_table
+                        .log_frame(
+                            region_table.frame_size_comp(i).map_err(io::Error::other)? as u32,
+                            region_table
+                                .frame_size_decomp(i)
+                                .map_err(io::Error::other)? as u32,
+                        )
+                        .map_err(io::Error::other)?;
+                }
+            }
+            Codec::Lz4 => {
+                let block_size = frame_size(compression.frame_size) as usize;
+                let mut block = Vec::with_capacity(block_size.min(CHUNK_SIZE));
+                loop {
+                    let n = read_full(&mut data, &mut chunk)?;
+                    if n == 0 {
+                        break;
+                    }
+                    let mut rest = &chunk[..n];
+                    while !rest.is_empty() {
+                        let cut = match cutter.as_mut() {
+                            Some(cutter) => cutter.next_cut(rest),
+                            None => Some(block_size - block.len()).filter(|&c| c <= rest.len()),
+                        };
+                        let Some(cut) = cut else {
+                            block.extend_from_slice(rest);
+                            break;
+                        };
+                        block.extend_from_slice(&rest[..cut]);
+                        self.lz4_block(&block)?;
+                        block.clear();
+                        rest = &rest[cut..];
+                    }
+                }
+                if !block.is_empty() {
+                    self.lz4_block(&block)?;
+                }
+            }
+            Codec::Stored => loop {
+                let n = read_full(&mut data, &mut chunk)?;
+                if n == 0 {
+                    break;
+                }
+                self.out.write_all(&chunk[..n])?;
+            },
+        }
+        Ok(self.out.count - start)
+    }
+
+    fn lz4_block(&mut self, block: &[u8]) -> io::Result<()> {
+        let compressed = lz4_flex::block::compress(block);
+        self.lz4_table
+            .extend((compressed.len() as u32).to_le_bytes());
+        self.lz4_table.extend((block.len() as u32).to_le_bytes());
+        self.out.write_all(&compressed)?;
+        self.lz4_blocks += 1;
+        Ok(())
+    }
+
+    /// Writes the stream's trailer: zstd's seek table, or the LZ4 block
+    /// table (compressed size (4 B LE), decompressed size (4 B LE)) per
+    /// block | block count (4 B LE)
+    pub fn finish(mut self) -> io::Result<W> {
+        match self.codec {
+            Codec::Zstd => {
+                let mut serializer = self.seek_table.into_serializer();
+                let mut table = vec![0; serializer.encoded_len()];
+                serializer.write_into(&mut table);
+                self.out.write_all(&table)?;
+            }
+            Codec::Lz4 => {
+                self.out.write_all(&self.lz4_table)?;
+                self.out.write_all(&self.lz4_blocks.to_le_bytes())?;
+            }
+            Codec::Stored => {}
+        }
+        self.out.flush()?;
+        Ok(self.out.inner)
+    }
+}
+
+fn frame_size(frame_size: FrameSize) -> u32 {
+    match frame_size {
+        FrameSize::Uncompressed(size) | FrameSize::Compressed(size) => size.max(1),
+    }
+}
+
+/// Random 64-bit values, one per byte, for [`FrameCutter`]'s gear hash
+/// (splitmix64)
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut x = 0u64;
+    let mut i = 0;
+    while i < table.len() {
+        x = x.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = x;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Content-defined frame boundaries: a frame ends where a rolling hash of
+/// its last 64 bytes has its top bits clear, once it's past half of the
+/// frame size, and at the frame size at the latest. An edit then only
+/// moves the boundaries next to it, so the frames after it compress to the
+/// same bytes as before and delta patches (see [`crate::patch`]) stay
+/// small.
+struct FrameCutter {
+    max_len: usize,
+    /// Bits of the hash that must be clear for a cut, so one comes every
+    /// quarter of the frame size on average
+    shift: u32,
+    len: usize,
+    hash: u64,
+}
+
+impl FrameCutter {
+    fn new(frame_size: u32) -> Self {
+        let max_len = frame_size.max(1) as usize;
+        FrameCutter {
+            max_len,
+            shift: 64 - (max_len / 4).max(1).ilog2(),
+            len: 0,
+            hash: 0,
+        }
+    }
+
+    /// How many bytes of `data` the current frame takes if it ends in it
+    fn next_cut(&mut self, data: &[u8]) -> Option<usize> {
+        for (i, &b) in data.iter().enumerate() {
+            self.hash = (self.hash << 1).wrapping_add(GEAR[b as usize]);
+            self.len += 1;
+            if self.len >= self.max_len
+                || (self.len >= self.max_len / 2 && (self.hash >> self.shift) == 0)
+            {
+                self.len = 0;
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+}
+
+/// Counts the bytes written through it
+struct Counting<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for Counting<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Fills `buf` unless `r` ends first, returns how much was read
+fn read_full<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match r.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+pub(crate) struct Lz4Block {
+    comp_start: u64,
+    comp_size: u32,
+    decomp_start: u64,
+    decomp_size: u32,
+}
+
+/// Random access to the decompressed stream. `R` must start at the stream
+/// and end with it.
+pub(crate) enum StreamDecoder<'a, R: Read + Seek> {
+    Zstd(zeekstd::Decoder<'a, R>),
+    Lz4 { reader: R, blocks: Vec<Lz4Block> },
+    Stored(R),
+}
+
+impl<R: Read + Seek> StreamDecoder<'_, R> {
+    pub(crate) fn new(codec: Codec, mut reader: R) -> Result<Self, Box<dyn std::error::Error>> {
+        match codec {
+            Codec::Zstd => Ok(StreamDecoder::Zstd(zeekstd::Decoder::new(reader)?)),
+            Codec::Lz4 => {
+                let mut buf = [0u8; 4];
+                let stream_len = reader.seek(SeekFrom::End(0))?;
+                reader.seek(SeekFrom::End(-4))?;
+                reader.read_exact(&mut buf)?;
+                let count = u32::from_le_bytes(buf) as usize;
+                // The count comes from the file, so check the table fits
+                // in the stream before allocating for it
+                let table_len = count
+                    .checked_mul(8)
+                    .filter(|&len| len as u64 <= stream_len - 4)
+                    .ok_or("LZ4 block table larger than the stream")?;
+                let mut table = vec![0u8; table_len];
+                reader.seek(SeekFrom::End(-4 - table.len() as i64))?;
+                reader.read_exact(&mut table)?;
+
+                let mut blocks = Vec::with_capacity(count);
+                let (mut comp_start, mut decomp_start) = (0u64, 0u64);
+                for entry in table.chunks_exact(8) {
+                    let comp_size = u32::from_le_bytes(entry[..4].try_into().unwrap());
+                    let decomp_size = u32::from_le_bytes(entry[4..].try_into().unwrap());
+                    blocks.push(Lz4Block {
+                        comp_start,
+                        comp_size,
+                        decomp_start,
+                        decomp_size,
+                    });
+                    comp_start += comp_size as u64;
+                    decomp_start += decomp_size as u64;
+                }
+                Ok(StreamDecoder::Lz4 { reader, blocks })
+            }
+            Codec::Stored => Ok(StreamDecoder::Stored(reader)),
+        }
+    }
+
+    /// Size of the whole decompressed stream
+    fn decompressed_len(&mut self) -> io::Result<u64> {
+        match self {
+            StreamDecoder::Zstd(decoder) => Ok(decoder.seek_table().size_decomp()),
+            StreamDecoder::Lz4 { blocks, .. } => Ok(blocks
+                .last()
+                .map_or(0, |b| b.decomp_start + b.decomp_size as u64)),
+            StreamDecoder::Stored(reader) => reader.seek(SeekFrom::End(0)),
+        }
+    }
+
+    /// Decompresses `len` bytes of the stream from `start`. Both come from
+    /// the file, so a range past the end of the stream is an error rather
+    /// than an allocation of its size.
+    pub(crate) fn read_at(
+        &mut self,
+        start: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let stream_len = self.decompressed_len()?;
+        let end = (start.checked_add(len))
+            .filter(|&end| end <= stream_len)
+            .ok_or("Range past the end of the stream")?;
+        let len = len as usize;
+        match self {
+            StreamDecoder::Zstd(decoder) => {
+                decoder.set_offset(start)?;
+                decoder.set_offset_limit(end)?;
+
+                let mut decompressed = vec![0u8; len];
+                let mut filled = 0;
+                while filled < decompressed.len() {
+                    // zeekstd 0.6 slices its output as `[progress..limit]`,
+                    // with `limit` what's left in the range rather than
+                    // `progress` plus that, so a call that writes more than
+                    // half of what's left panics on its next input read.
+                    // Asking for at most half keeps `limit` past `progress`.
+                    let chunk = ((decompressed.len() - filled) / 2).max(1);
+                    let n = decoder.decompress(&mut decompressed[filled..filled + chunk])?;
+                    if n == 0 {
+                        return Err("Unexpected end of compressed stream".into());
+                    }
+                    filled += n;
+                }
+                Ok(decompressed)
+            }
+            StreamDecoder::Lz4 { reader, blocks } => {
+                let mut decompressed = Vec::with_capacity(len);
+                let first =
+                    blocks.partition_point(|b| b.decomp_start + b.decomp_size as u64 <= start);
+                for block in blocks[first..].iter().take_while(|b| b.decomp_start < end) {
+                    let mut compressed = vec![0u8; block.comp_size as usize];
+                    reader.seek(SeekFrom::Start(block.comp_start))?;
+                    reader.read_exact(&mut compressed)?;
+                    let data =
+                        lz4_flex::block::decompress(&compressed, block.decomp_size as usize)?;
+                    if data.len() != block.decomp_size as usize {
+                        return Err("Malformed LZ4 block".into());
+                    }
+                    let from = start.saturating_sub(block.decomp_start) as usize;
+                    let to = (end - block.decomp_start).min(block.decomp_size as u64) as usize;
+                    decompressed.extend_from_slice(&data[from..to]);
+                }
+                if decompressed.len() != len {
+                    return Err("Unexpected end of compressed stream".into());
+                }
+                Ok(decompressed)
+            }
+            StreamDecoder::Stored(reader) => {
+                let mut data = vec![0u8; len];
+                reader.seek(SeekFrom::Start(start))?;
+                reader.read_exact(&mut data)?;
+                Ok(data)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Incompressible, so reading a range takes several input reads
+    fn noise(len: usize) -> Vec<u8> {
+        let mut x = 1u64;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_read_at() {
+        let data = noise(1 << 20);
+        let compression = RegionCompression {
+            level: 3,
+            frame_size: FrameSize::Uncompressed(64 * 1024),
+        };
+        for codec in [Codec::Zstd, Codec::Lz4, Codec::Stored] {
+            let mut encoder = StreamEncoder::new(codec, Vec::new());
+            encoder.region(data.as_slice(), compression).unwrap();
+            let stream = encoder.finish().unwrap();
+
+            // Ranges within a frame and across several, up to the end
+            let mut decoder = StreamDecoder::new(codec, Cursor::new(&stream)).unwrap();
+            let mut ranges = vec![(0, 1 << 20), (1000, 899_000), (5, 1), (1 << 20, 0)];
+            ranges.extend((0..20).map(|i| (i * 50_000, 20_000)));
+            for (start, len) in ranges {
+                let range = decoder.read_at(start, len).unwrap();
+                assert!(
+                    range == data[start as usize..(start + len) as usize],
+                    "{:?}",
+                    codec
+                );
+            }
+
+            for (start, len) in [(1 << 20, 1), (1000, 1 << 20), (u64::MAX, 2)] {
+                let err = decoder.read_at(start, len).err().unwrap();
+                assert_eq!(err.to_string(), "Range past the end of the stream");
+            }
+        }
+    }
+
+    #[test]
+    fn test_lz4_block_count() {
+        let mut encoder = StreamEncoder::new(Codec::Lz4, Vec::new());
+        encoder.frame(&noise(1000), 0).unwrap();
+        let mut stream = encoder.finish().unwrap();
+        assert!(StreamDecoder::new(Codec::Lz4, Cursor::new(&stream)).is_ok());
+
+        let end = stream.len();
+        stream[end - 4..].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = StreamDecoder::new(Codec::Lz4, Cursor::new(&stream))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "LZ4 block table larger than the stream");
+    }
+
+    #[test]
+    fn test_codecs() {
+        use crate::Compression;
+        use crate::reader::DictionaryReader;
+        use crate::test_util::mono_words;
+        use crate::writer::{DictionaryWriter, WriterOptions};
+
+        let words: Vec<String> = (0..300).map(|i| format!("sana{}", i)).collect();
+        let tagged_words = mono_words(&words);
+
+        for codec in [Codec::Zstd, Codec::Lz4, Codec::Stored] {
+            // Small frames, so lookups span several LZ4 blocks
+            let region = RegionCompression {
+                level: 3,
+                frame_size: FrameSize::Uncompressed(300),
+            };
+            let opts = WriterOptions {
+                codec,
+                compression: Compression {
+                    level2: region,
+                    data: region,
+                },
+                ..Default::default()
+            };
+            let writer = DictionaryWriter::new().options(opts).timestamp(0);
+            let mut buffer = Vec::new();
+            let report = writer.write(&mut buffer, &tagged_words, &[]).unwrap();
+            // Only the stored stream keeps every word readable; the
+            // others only where a frame starts
+            let readable = buffer.windows(4).filter(|w| w == b"sana").count();
+            assert_eq!(readable >= words.len(), codec == Codec::Stored);
+
+            let mut dict_reader = DictionaryReader::open(Cursor::new(buffer.clone())).unwrap();
+            assert_eq!(dict_reader.codec(), codec);
+            for w in &words {
+                let found = dict_reader.lookup(w).unwrap().expect("word missing");
+                assert_eq!(found.entries[0].senses[0].glosses[0].gloss_lines[0], *w);
+            }
+            assert!(dict_reader.lookup("sana300").unwrap().is_none());
+
+            // A malformed Level 2 entry (empty suffix) is an error, not a
+            // panic
+            if codec == Codec::Stored {
+                let stream_start = (report.file_size - report.stream_size) as usize;
+                assert_eq!(&buffer[stream_start..stream_start + 7], b"\x00\x05sana0");
+                buffer[stream_start + 1] = 0;
+                let mut dict_reader = DictionaryReader::open(Cursor::new(buffer)).unwrap();
+                assert!(dict_reader.lookup("sana0").is_err());
+            }
+        }
+    }
+}
@@ -38,6 +38,8 @@ pub enum MaxLen {
     OneByte,
     TwoBytes,
     TwoBytesVar,
+    /// `VarU64` count, no practical limit
+    Var,
 }
 
 pub trait CompactDeserialize: Sized {
@@ -87,9 +89,11 @@ impl<T: CompactDeserialize> CompactDeserializeWithMaxLen for Vec<T> {
                 let vu = VarUint::deserialize(input)?;
                 vu.0 as usize
             }
+            MaxLen::Var => VarU64::deserialize(input)?.0 as usize,
         };
 
-        let mut vec = Vec::with_capacity(len);
+        // Don't trust the count for the allocation, the data may be corrupt
+        let mut vec = Vec::with_capacity(len.min(1024));
         for _ in 0..len {
             vec.push(T::deserialize(input)?);
         }
@@ -147,4 +151,23 @@ impl CompactDeserialize for VarUint {
     }
 }
 
+/// LEB128: 7 bits per byte, low bits first, high bit set on all but the
+/// last byte
+pub struct VarU64(pub u64);
+
+impl CompactDeserialize for VarU64 {
+    fn deserialize<R: Read>(input: &mut R) -> Result<Self, DeserializeError> {
+        let mut value = 0u64;
+        let mut buf = [0u8; 1];
+        for shift in (0..64).step_by(7) {
+            input.read_exact(&mut buf)?;
+            value |= ((buf[0] & 0x7F) as u64) << shift;
+            if buf[0] & 0x80 == 0 {
+                return Ok(VarU64(value));
+            }
+        }
+        Err(DeserializeError::InvalidData("VarU64 too long"))
+    }
+}
+
 pub use tarkka_derive::CompactDeserialize;
@@ -0,0 +1,93 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::time::Instant;
+
+use tarkka::merge::{MergePolicy, merge_dictionaries};
+use tarkka::reader::DictionaryReader;
+use tarkka::writer::{DictionaryWriter, WriterOptions};
+
+/// `--string-table` when the base dictionary has a string table: the file
+/// doesn't record the count it was built with
+const STRING_TABLE_MIN_COUNT: u32 = 2;
+
+fn usage(prog: &str) -> ! {
+    eprintln!(
+        "Usage: {} [--policy <first|union|all>] [--string-table <N>] <out.dict> <base.dict> <other.dict>...",
+        prog
+    );
+    eprintln!("  first: a word's entry from the first dictionary that has it (default)");
+    eprintln!("  union: senses of all of them, merged by part of speech");
+    eprintln!("  all:   senses of all of them, one after another");
+    eprintln!("  --string-table <N>: intern gloss lines repeated at least N times, 0 for none");
+    eprintln!(
+        "    (default: {} if the base has a string table, else 0)",
+        STRING_TABLE_MIN_COUNT
+    );
+    std::process::exit(1);
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args: Vec<String> = std::env::args().collect();
+    let prog = args.remove(0);
+    let mut policy = MergePolicy::PreferFirst;
+    if let Some(i) = args.iter().position(|a| a == "--policy") {
+        match args.get(i + 1).map(|p| p.parse()) {
+            Some(Ok(p)) => policy = p,
+            _ => usage(&prog),
+        }
+        args.drain(i..i + 2);
+    }
+    let mut string_table = None;
+    if let Some(i) = args.iter().position(|a| a == "--string-table") {
+        match args.get(i + 1).map(|n| n.parse()) {
+            Some(Ok(n)) => string_table = Some(n),
+            _ => usage(&prog),
+        }
+        args.drain(i..i + 2);
+    }
+    if args.len() < 3 {
+        usage(&prog);
+    }
+
+    let s = Instant::now();
+    let mut dictionaries = Vec::new();
+    for path in &args[1..] {
+        dictionaries.push(DictionaryReader::open(File::open(path)?)?);
+    }
+    // Laid out and compressed like the base dictionary, with its hot words
+    let base = &mut dictionaries[0];
+    let default_string_table = match base.has_string_table() {
+        true => STRING_TABLE_MIN_COUNT,
+        false => 0,
+    };
+    let hot_words: Vec<String> = base.hot_words().into_iter().map(String::from).collect();
+    let mut opts = WriterOptions {
+        grouping: base.grouping(),
+        codec: base.codec(),
+        offsets: base.offsets(),
+        layout: base.layout(),
+        restart_interval: base.restart_interval(),
+        mphf: base.has_mphf(),
+        fst: base.has_fst(),
+        bloom_bits_per_key: base.bloom_bits_per_key()?,
+        string_table_min_count: string_table.unwrap_or(default_string_table),
+        hot_words: hot_words.len(),
+        frequency_list: hot_words,
+        ..Default::default()
+    };
+    if let Some(compression) = base.compression() {
+        opts.compression = compression;
+    }
+
+    let writer = DictionaryWriter::new().options(opts);
+    let out = BufWriter::new(File::create(&args[0])?);
+    let report = merge_dictionaries(&mut dictionaries, policy, &writer, out)?;
+    println!(
+        "Created {}: {} words from {} dictionaries, took {:?}",
+        args[0],
+        report.word_count,
+        dictionaries.len(),
+        s.elapsed()
+    );
+    Ok(())
+}
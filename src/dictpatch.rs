@@ -0,0 +1,43 @@
+use std::time::Instant;
+
+use tarkka::patch;
+
+fn usage(prog: &str) -> ! {
+    eprintln!("Usage:");
+    eprintln!("  {} diff <old.dict> <new.dict> <out.patch>", prog);
+    eprintln!("  {} apply <old.dict> <in.patch> <out.dict>", prog);
+    std::process::exit(1);
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 5 {
+        usage(&args[0]);
+    }
+
+    let s = Instant::now();
+    match args[1].as_str() {
+        "diff" => {
+            let old = std::fs::read(&args[2])?;
+            let new = std::fs::read(&args[3])?;
+            let p = patch::diff(&old, &new);
+            std::fs::write(&args[4], &p)?;
+            println!(
+                "Created {}: {} bytes for a {} byte file, took {:?}",
+                args[4],
+                p.len(),
+                new.len(),
+                s.elapsed()
+            );
+        }
+        "apply" => {
+            let old = std::fs::read(&args[2])?;
+            let p = std::fs::read(&args[3])?;
+            let new = patch::apply(&old, &p)?;
+            std::fs::write(&args[4], &new)?;
+            println!("Created {}, took {:?}", args[4], s.elapsed());
+        }
+        _ => usage(&args[0]),
+    }
+    Ok(())
+}
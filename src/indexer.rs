@@ -1,8 +1,8 @@
-use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read, Seek, Write};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek};
 use std::path::Path;
 use std::sync::{
     Arc, Mutex,
@@ -11,9 +11,14 @@ use std::sync::{
 use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use tarkka::kaikki::KaikkiWordEntry;
 use tarkka::reader::DictionaryReader;
+use tarkka::signature;
+use tarkka::streaming::StreamingBuilder;
+use tarkka::writer::{
+    BuildReport, DictionaryWriter, InflectedForm, WriterOptions, build_forms_index,
+    build_tagged_index, source_date_epoch,
+};
 use tarkka::{
-    HEADER_SIZE, PartOfSpeech, TARKKA_FMT_VERSION, WordEntryComplete, WordTag,
-    WordWithTaggedEntries,
+    FrameSize, Grouping, Offsets, StreamLayout, TARKKA_FMT_VERSION, WordTag, WordWithTaggedEntries,
 };
 use threadpool::ThreadPool;
 
@@ -34,32 +39,78 @@ const SUPPORTED_LANGUAGES: &[&str] = &[
     "pt", "ro", "ru", "sk", "sl", "es", "sv", "ta", "te", "tr", "uk", "is",
 ];
 
-pub mod reader;
-pub mod ser;
-use tarkka::ser::{CompactSerialize, VarUint};
-use zeekstd::{EncodeOptions, FrameSizePolicy};
-
-fn lang_words(word_lang: &str, gloss_lang: &str, fname: &str) -> Vec<WordWithTaggedEntries> {
+fn lang_words(
+    word_lang: &str,
+    gloss_lang: &str,
+    fname: &str,
+) -> (Vec<WordWithTaggedEntries>, Vec<InflectedForm>) {
     let f = File::open(fname).unwrap();
     let s = Instant::now();
     let good_words = filter(f);
     println!("Filter took {:?}", s.elapsed());
-    let tag = match (word_lang, gloss_lang) {
-        (_, "en") => WordTag::English,
-        (x, y) if x == y => WordTag::Monolingual,
-        (_, _) => panic!("idk what to do {word_lang} {gloss_lang}"),
-    };
+    let tag = word_tag(word_lang, gloss_lang);
+    let mut forms = Vec::new();
     let filtered: Vec<WordWithTaggedEntries> = good_words
         .into_iter()
-        .map(|w| w.to_word_entry_complete(tag))
+        .map(|w| {
+            for (form, tags) in w.inflected_forms() {
+                forms.push(InflectedForm {
+                    form,
+                    lemma: w.word.clone(),
+                    tags,
+                });
+            }
+            w.to_word_entry_complete(tag)
+        })
         .collect();
 
-    filtered
+    (filtered, forms)
+}
+
+/// Like `lang_words`, but adds each entry to `builder` as it's parsed.
+/// Returns the entry count.
+fn stream_lang_words(
+    word_lang: &str,
+    gloss_lang: &str,
+    fname: &str,
+    builder: &mut StreamingBuilder,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let tag = word_tag(word_lang, gloss_lang);
+    let mut count = 0;
+    for line in BufReader::new(File::open(fname)?).lines() {
+        let w = parse_entry(&line?)?;
+        for (form, tags) in w.inflected_forms() {
+            builder.add_form(InflectedForm {
+                form,
+                lemma: w.word.clone(),
+                tags,
+            })?;
+        }
+        let entry = w.to_word_entry_complete(tag);
+        match tag {
+            WordTag::English => builder.add_english(&entry)?,
+            _ => builder.add_monolingual(&entry)?,
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn word_tag(word_lang: &str, gloss_lang: &str) -> WordTag {
+    match (word_lang, gloss_lang) {
+        (_, "en") => WordTag::English,
+        (x, y) if x == y => WordTag::Monolingual,
+        (_, _) => panic!("idk what to do {word_lang} {gloss_lang}"),
+    }
 }
 
 fn create_dictionary(
     lang: &str,
     timestamp_s: u64,
+    shard_words: Option<usize>,
+    max_memory: Option<usize>,
+    check_reproducible: bool,
+    opts: &WriterOptions,
 ) -> Result<(String, String, u32, u64), Box<dyn std::error::Error>> {
     println!("Processing: {}", lang);
 
@@ -67,118 +118,382 @@ fn create_dictionary(
     let english_path = format!("out/english/{}.jsonl", lang);
 
     // Check what files are available
-    let has_monolingual = Path::new(&monolingual_path).exists();
-    let has_english = Path::new(&english_path).exists();
+    let sources = Sources {
+        monolingual: Some(monolingual_path).filter(|p| Path::new(p).exists()),
+        english: Some(english_path).filter(|p| Path::new(p).exists()),
+    };
 
-    if !has_monolingual && !has_english {
+    if sources.monolingual.is_none() && sources.english.is_none() {
         return Err(format!("No files available for {}", lang).into());
     }
 
     let output_filename = format!("out/dictionaries/{}/{}.dict", TARKKA_FMT_VERSION, lang);
 
     // Determine dictionary type
-    let dict_type = if has_monolingual && has_english {
-        "bilingual"
-    } else if has_english {
-        "english"
-    } else {
-        "monolingual"
+    let dict_type = match (&sources.monolingual, &sources.english) {
+        (Some(_), Some(_)) => "bilingual",
+        (None, Some(_)) => "english",
+        _ => "monolingual",
     };
 
     // Check if output file already exists
-    if Path::new(&output_filename).exists() {
+    let (word_count, timestamp_s) = if Path::new(&output_filename).exists() {
         let r = DictionaryReader::open(std::fs::File::open(&output_filename).unwrap()).unwrap();
-        println!("Dictionary already exists, skipping: {}", output_filename);
-        return Ok((
-            output_filename,
-            dict_type.to_string(),
-            r.word_count(),
-            r.created_at().duration_since(UNIX_EPOCH)?.as_secs(),
-        ));
+        if !check_reproducible {
+            println!("Dictionary already exists, skipping: {}", output_filename);
+        }
+        let created_at = r.created_at().duration_since(UNIX_EPOCH)?.as_secs();
+        (r.word_count(), created_at)
+    } else {
+        let writer = DictionaryWriter::new()
+            .options(opts.clone())
+            .timestamp(timestamp_s);
+        let report = build_dictionary(
+            lang,
+            &sources,
+            &output_filename,
+            &writer,
+            shard_words,
+            max_memory,
+        )?;
+        // Next to the `.dict`, so CI can track its sizes across builds
+        let report_filename = output_filename.replace(".dict", ".report.json");
+        std::fs::write(&report_filename, serde_json::to_string_pretty(&report)?)?;
+        (report.word_count, timestamp_s)
+    };
+
+    // Rebuild with the same timestamp, so only the content is compared
+    if check_reproducible {
+        let check_filename = format!("{}.check", output_filename);
+        let writer = DictionaryWriter::new()
+            .options(opts.clone())
+            .timestamp(timestamp_s);
+        build_dictionary(lang, &sources, &check_filename, &writer, None, max_memory)?;
+        let difference = first_difference(&output_filename, &check_filename)?;
+        std::fs::remove_file(&check_filename)?;
+        match difference {
+            Some(offset) => {
+                return Err(Box::new(NotReproducible {
+                    filename: output_filename,
+                    offset,
+                }));
+            }
+            None => println!("Reproducible: {}\n", output_filename),
+        }
+    }
+
+    Ok((
+        output_filename,
+        dict_type.to_string(),
+        word_count,
+        timestamp_s,
+    ))
+}
+
+/// Rebuilding `filename` gave different bytes, from `offset` on
+#[derive(Debug)]
+struct NotReproducible {
+    filename: String,
+    offset: u64,
+}
+
+impl fmt::Display for NotReproducible {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is not reproducible: rebuilding it differs at byte {}",
+            self.filename, self.offset
+        )
+    }
+}
+
+impl std::error::Error for NotReproducible {}
+
+/// A language's Kaikki extracts, where they exist
+struct Sources {
+    monolingual: Option<String>,
+    english: Option<String>,
+}
+
+/// Builds `lang`'s dictionary into `output_filename`, and its shards with
+/// `shard_words`. Returns the word count.
+fn build_dictionary(
+    lang: &str,
+    sources: &Sources,
+    output_filename: &str,
+    writer: &DictionaryWriter,
+    shard_words: Option<usize>,
+    max_memory: Option<usize>,
+) -> Result<BuildReport, Box<dyn std::error::Error>> {
+    if let Some(max_memory) = max_memory {
+        let s = Instant::now();
+        // Scratch files go next to the output, on the same disk
+        let dir = Path::new(output_filename)
+            .parent()
+            .unwrap_or(Path::new("."));
+        let mut builder = StreamingBuilder::new(writer.clone(), dir, max_memory);
+        if let Some(path) = &sources.monolingual {
+            let count = stream_lang_words(lang, lang, path, &mut builder)?;
+            println!("entries {} (mono) {}", lang.to_uppercase(), count);
+        }
+        if let Some(path) = &sources.english {
+            let count = stream_lang_words(lang, "en", path, &mut builder)?;
+            println!("entries {} (eng) {}", lang.to_uppercase(), count);
+        }
+        let report = builder.finish(BufWriter::new(File::create(output_filename)?))?;
+        println!("Streaming build took {:?}", s.elapsed());
+        println!("Created: {}\n", output_filename);
+        return Ok(report);
     }
 
     // Load available data
-    let (good_words1, good_words2) = if has_monolingual && has_english {
-        // Both available - multi dictionary
-        let mono = lang_words(lang, lang, &monolingual_path);
-        let eng = lang_words(lang, "en", &english_path);
-        println!(
-            "entries {} (mono) {} {} (eng) {}",
-            lang.to_uppercase(),
-            mono.len(),
-            lang.to_uppercase(),
-            eng.len()
-        );
-        (mono, eng)
-    } else if has_english {
-        // Only English available - english dictionary
-        let eng = lang_words(lang, "en", &english_path);
-        println!("entries {} (eng) {}", lang.to_uppercase(), eng.len());
-        (Vec::new(), eng)
-    } else {
-        // Only monolingual available - treat as multi but with empty English
-        let mono = lang_words(lang, lang, &monolingual_path);
-        println!("entries {} (mono) {}", lang.to_uppercase(), mono.len());
-        (mono, Vec::new())
+    let (good_words1, good_words2, forms) = match (&sources.monolingual, &sources.english) {
+        (Some(monolingual_path), Some(english_path)) => {
+            // Both available - multi dictionary
+            let (mono, mut forms) = lang_words(lang, lang, monolingual_path);
+            let (eng, eng_forms) = lang_words(lang, "en", english_path);
+            forms.extend(eng_forms);
+            println!(
+                "entries {} (mono) {} {} (eng) {}",
+                lang.to_uppercase(),
+                mono.len(),
+                lang.to_uppercase(),
+                eng.len()
+            );
+            (mono, eng, forms)
+        }
+        (None, Some(english_path)) => {
+            // Only English available - english dictionary
+            let (eng, forms) = lang_words(lang, "en", english_path);
+            println!("entries {} (eng) {}", lang.to_uppercase(), eng.len());
+            (Vec::new(), eng, forms)
+        }
+        (Some(monolingual_path), None) => {
+            // Only monolingual available - treat as multi but with empty English
+            let (mono, forms) = lang_words(lang, lang, monolingual_path);
+            println!("entries {} (mono) {}", lang.to_uppercase(), mono.len());
+            (mono, Vec::new(), forms)
+        }
+        (None, None) => return Err(format!("No files available for {}", lang).into()),
     };
 
     let s = Instant::now();
-    let words = build_tagged_index(good_words1, good_words2);
+    let words = build_tagged_index(good_words1, good_words2)?;
+    let forms = build_forms_index(forms, &words);
     println!("Build index took {:?}", s.elapsed());
 
     let s = Instant::now();
-
-    let file = File::create(&output_filename)?;
-    let word_count = write_tagged(file, words, timestamp_s);
+    let file = File::create(output_filename)?;
+    let report = writer.write(file, &words, &forms)?;
     println!("Writing took {:?}", s.elapsed());
     println!("Created: {}\n", output_filename);
 
-    Ok((
-        output_filename,
-        dict_type.to_string(),
-        word_count,
-        timestamp_s,
-    ))
+    if let Some(shard_words) = shard_words {
+        let shard_dir = format!("out/dictionaries/{}/shards/{}", TARKKA_FMT_VERSION, lang);
+        let manifest =
+            writer.write_sharded(Path::new(&shard_dir), lang, &words, &forms, shard_words)?;
+        println!("Created {} shards in {}", manifest.shards.len(), shard_dir);
+    }
+
+    Ok(report)
+}
+
+/// Offset of the first byte where files `a` and `b` differ, if they do
+fn first_difference(a: &str, b: &str) -> std::io::Result<Option<u64>> {
+    let (mut a, mut b) = (
+        BufReader::new(File::open(a)?),
+        BufReader::new(File::open(b)?),
+    );
+    let mut offset = 0u64;
+    loop {
+        let (chunk_a, chunk_b) = (a.fill_buf()?, b.fill_buf()?);
+        if chunk_a.is_empty() && chunk_b.is_empty() {
+            return Ok(None);
+        }
+        let n = chunk_a.len().min(chunk_b.len());
+        match chunk_a[..n]
+            .iter()
+            .zip(&chunk_b[..n])
+            .position(|(x, y)| x != y)
+        {
+            Some(i) => return Ok(Some(offset + i as u64)),
+            // One file ended before the other
+            None if n == 0 => return Ok(Some(offset)),
+            None => {}
+        }
+        a.consume(n);
+        b.consume(n);
+        offset += n as u64;
+    }
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    // --shard-words <N>: also split each dictionary into shards of ~N words
+    let shard_words: Option<usize> = flag_value(&args, "--shard-words");
+    // --max-memory <MiB>: build with the streaming builder, buffering about
+    // this much per language (several are built at once) before spilling
+    // sorted runs to disk. Can't be combined with --shard-words.
+    let max_memory: Option<usize> = flag_value(&args, "--max-memory");
+    if max_memory.is_some() && shard_words.is_some() {
+        eprintln!("--max-memory can't be combined with --shard-words");
+        std::process::exit(1);
+    }
+    // --check-reproducible: rebuild each dictionary (built now or before)
+    // with the same timestamp, and fail unless it comes out byte for byte
+    // the same. Set SOURCE_DATE_EPOCH to timestamp new builds with it.
+    let check_reproducible = args.iter().any(|a| a == "--check-reproducible");
+
+    // --{l2,data}-level <N>, --{l2,data}-frame-size <BYTES>: zstd level and
+    // uncompressed frame size of the Level 2 groups and of the binary data
+    let mut opts = WriterOptions::default();
+    let regions = [
+        ("l2", &mut opts.compression.level2),
+        ("data", &mut opts.compression.data),
+    ];
+    for (name, region) in regions {
+        if let Some(level) = flag_value(&args, &format!("--{}-level", name)) {
+            region.level = level;
+        }
+        if let Some(size) = flag_value(&args, &format!("--{}-frame-size", name)) {
+            region.frame_size = FrameSize::Uncompressed(size);
+        }
+    }
+    // --max-group-size <BYTES>: bounded Level 2 groups instead of 3 byte
+    // prefixes
+    if let Some(max_group_size) = flag_value(&args, "--max-group-size") {
+        opts.grouping = Grouping::Bounded { max_group_size };
+    }
+    // --restart-interval <N>: entries between restart points, 0 for none
+    if let Some(interval) = flag_value(&args, "--restart-interval") {
+        opts.restart_interval = interval;
+    }
+    // --mphf: index the words for exact lookups without decompressing
+    // Level 2
+    if args.iter().any(|a| a == "--mphf") {
+        opts.mphf = true;
+    }
+    // --fst: index the words for regex, fuzzy and range queries
+    if args.iter().any(|a| a == "--fst") {
+        opts.fst = true;
+    }
+    // --bloom-bits <N>: bloom filter with N bits per key, for fast misses
+    if let Some(bits) = flag_value(&args, "--bloom-bits") {
+        opts.bloom_bits_per_key = bits;
+    }
+    // --interleave: put each group's binary data right after it
+    if args.iter().any(|a| a == "--interleave") {
+        opts.layout = StreamLayout::Interleaved;
+    }
+    // --codec <zstd|lz4|stored>: how the stream is compressed
+    if let Some(codec) = flag_value(&args, "--codec") {
+        opts.codec = codec;
+    }
+    // --string-table <N>: intern gloss lines repeated at least N times
+    if let Some(min_count) = flag_value(&args, "--string-table") {
+        opts.string_table_min_count = min_count;
+    }
+    // --frequency-lists <DIR>: rank each language's words by <DIR>/<lang>.txt
+    // (most frequent first) and put the top --hot-words <N> (default 5000)
+    // in a hot frame
+    let frequency_lists: Option<String> = flag_value(&args, "--frequency-lists");
+    let hot_words = flag_value(&args, "--hot-words").unwrap_or(5000);
+    // --signing-key <PATH>: sign with the ed25519 seed in PATH (32 raw bytes)
+    if let Some(path) = flag_value::<String>(&args, "--signing-key") {
+        let key = std::fs::read(&path)
+            .ok()
+            .and_then(|k| <[u8; 32]>::try_from(k).ok());
+        let Some(key) = key else {
+            eprintln!("{} must hold a 32 byte ed25519 seed", path);
+            std::process::exit(1);
+        };
+        let public_key = signature::public_key(&key);
+        println!(
+            "Signing with public key {}",
+            public_key
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        );
+        opts.signing_key = Some(key);
+    }
+    // --varint-offsets: for dictionaries over 4 GiB uncompressed
+    if args.iter().any(|a| a == "--varint-offsets") {
+        opts.offsets = Offsets::Varint;
+    }
+
     let pool = ThreadPool::new(12);
     let created_dictionaries = Arc::new(AtomicUsize::new(0));
     let skipped_languages = Arc::new(AtomicUsize::new(0));
-    let dictionary_metadata = Arc::new(Mutex::new(HashMap::new()));
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
+    let not_reproducible = Arc::new(AtomicUsize::new(0));
+    // Sorted by language, so index.json doesn't change between runs
+    let dictionary_metadata = Arc::new(Mutex::new(BTreeMap::new()));
+    let now = match source_date_epoch() {
+        Ok(Some(timestamp_s)) => timestamp_s,
+        Ok(None) => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
     for &lang in SUPPORTED_LANGUAGES {
+        let mut opts = opts.clone();
+        if let Some(dir) = &frequency_lists {
+            let path = Path::new(dir).join(format!("{}.txt", lang));
+            if path.exists() {
+                opts.frequency_list = read_frequency_list(&path).unwrap_or_else(|e| {
+                    eprintln!("Can't read {}: {}", path.display(), e);
+                    std::process::exit(1);
+                });
+                opts.hot_words = hot_words;
+            }
+        }
         let created_ref = Arc::clone(&created_dictionaries);
         let skipped_ref = Arc::clone(&skipped_languages);
         let metadata_ref = Arc::clone(&dictionary_metadata);
-
-        pool.execute(move || match create_dictionary(lang, now) {
-            Ok((filename, dict_type, word_count, timestamp)) => {
-                created_ref.fetch_add(1, Ordering::Relaxed);
-
-                if let Ok(metadata) = std::fs::metadata(&filename) {
-                    let size = metadata.len();
-                    let dict_filename = format!("{}.dict", lang);
-
-                    let dict_meta = DictionaryMetadata {
-                        size,
-                        filename: dict_filename,
-                        date: timestamp,
-                        dict_type,
-                        word_count,
-                    };
-
-                    let mut map = metadata_ref.lock().unwrap();
-                    map.insert(lang.to_string(), dict_meta);
+        let not_reproducible_ref = Arc::clone(&not_reproducible);
+
+        pool.execute(move || {
+            let max_memory = max_memory.map(|m| m << 20);
+            match create_dictionary(
+                lang,
+                now,
+                shard_words,
+                max_memory,
+                check_reproducible,
+                &opts,
+            ) {
+                Ok((filename, dict_type, word_count, timestamp)) => {
+                    created_ref.fetch_add(1, Ordering::Relaxed);
+
+                    if let Ok(metadata) = std::fs::metadata(&filename) {
+                        let size = metadata.len();
+                        let dict_filename = format!("{}.dict", lang);
+
+                        let dict_meta = DictionaryMetadata {
+                            size,
+                            filename: dict_filename,
+                            date: timestamp,
+                            dict_type,
+                            word_count,
+                        };
+
+                        let mut map = metadata_ref.lock().unwrap();
+                        map.insert(lang.to_string(), dict_meta);
+                    }
+                }
+                Err(e) if e.downcast_ref::<NotReproducible>().is_some() => {
+                    println!("{}", e);
+                    not_reproducible_ref.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    println!("Skipping {}: {}", lang, e);
+                    skipped_ref.fetch_add(1, Ordering::Relaxed);
                 }
-            }
-            Err(e) => {
-                println!("Skipping {}: {}", lang, e);
-                skipped_ref.fetch_add(1, Ordering::Relaxed);
             }
         });
     }
@@ -192,6 +507,11 @@ fn main() {
         "Completed: {} dictionaries created, {} languages skipped",
         created, skipped
     );
+    let not_reproducible = not_reproducible.load(Ordering::Relaxed);
+    if not_reproducible > 0 {
+        eprintln!("{} dictionaries are not reproducible", not_reproducible);
+        std::process::exit(1);
+    }
 
     // Create index.json
     let metadata_map = dictionary_metadata.lock().unwrap();
@@ -217,162 +537,31 @@ fn main() {
     }
 }
 
-fn common_prefix_len(a: &str, b: &str) -> usize {
-    a.bytes().zip(b.bytes()).take_while(|(x, y)| x == y).count()
-}
-
-pub fn write_tagged<W: Write>(
-    mut w: W,
-    sorted_words: Vec<WordWithTaggedEntries>,
-    timestamp_s: u64,
-) -> u32 {
-    let s = Instant::now();
-    let mut groups: BTreeMap<[u8; 3], Vec<&WordWithTaggedEntries>> = BTreeMap::new();
-    for word in &sorted_words {
-        let b = word.word.as_bytes();
-        let l1_group = match b.len() {
-            0 => panic!("got empty word"),
-            1 => [0, 0, b[0]],
-            2 => [0, b[0], b[1]],
-            _ => [b[0], b[1], b[2]],
-        };
-        groups.entry(l1_group).or_default().push(&word);
-    }
-
-    for group in groups.values_mut() {
-        group.sort_by(|a, b| a.word.as_bytes().cmp(&b.word.as_bytes()));
-    }
-    println!("grouped {:?}", s.elapsed());
-
-    let mut level1_data = Vec::with_capacity(64 * 4096);
-
-    let mut output = Vec::with_capacity(32 * 1024 * 1024);
-    let mut all_serialized = Vec::with_capacity(32 * 1024 * 1024);
-    let opts = EncodeOptions::new()
-        .checksum_flag(false)
-        .compression_level(9)
-        .frame_size_policy(FrameSizePolicy::Uncompressed(1024 * 1024));
-    let mut encoder = zeekstd::Encoder::with_opts(&mut output, opts).unwrap();
-    let mut level2_size: u32 = 0;
-
-    let mut shared_prefixes = 0;
-    let mut global_binary_offset = 0u32;
-
-    let mut under_1b = 0;
-    let mut under_2b = 0;
-    let mut over_2b = 0;
-    let mut word_count = 0u32;
-    for (l1_group, words) in groups {
-        let mut l2_raw_size = 0u32;
-        let mut prev_word = "";
-        let group_binary_start = global_binary_offset;
-
-        for word in words {
-            word_count += 1;
-            let current_word = &word.word;
-            let shared_len = common_prefix_len(prev_word, current_word);
-            let suffix = &current_word.as_bytes()[shared_len..];
-
-            shared_prefixes += shared_len;
-            assert!(shared_len <= 127, "Shared prefix too long: {}", shared_len);
-            assert!(suffix.len() <= 255, "Suffix too long: {}", suffix.len());
-            assert!(
-                suffix.len() > 0,
-                "No suffix = duplicated word? {}",
-                word.word
-            );
-
-            // {"word": "こんにちは", "lang": "Japanese", "lang_code": "ja", "redirects": ["今日は"], "pos": "soft-redirect", "senses": [{"tags": ["no-gloss"]}]}
-            let ser_size = word.serialize(&mut all_serialized).unwrap();
-            assert!(
-                ser_size <= (u16::MAX / 2u16) as usize,
-                "word too long {:#?}",
-                word
+/// Parses the value following `flag`, exiting with the usage on bad input
+fn flag_value<T: std::str::FromStr>(args: &[String], flag: &str) -> Option<T> {
+    let i = args.iter().position(|a| a == flag)?;
+    match args.get(i + 1).and_then(|n| n.parse().ok()) {
+        Some(n) => Some(n),
+        None => {
+            eprintln!(
+                "Usage: {} [--shard-words <N>] [--max-memory <MiB>] [--check-reproducible] [--l2-level <N>] [--l2-frame-size <BYTES>] [--data-level <N>] [--data-frame-size <BYTES>] [--max-group-size <BYTES>] [--restart-interval <N>] [--mphf] [--fst] [--bloom-bits <N>] [--codec <zstd|lz4|stored>] [--interleave] [--string-table <N>] [--frequency-lists <DIR>] [--hot-words <N>] [--signing-key <PATH>] [--varint-offsets]",
+                args[0]
             );
-            let ss: VarUint = ser_size.into();
-            if ser_size < 127 {
-                under_1b += 1;
-            } else if ser_size < 32767 {
-                under_2b += 1;
-            } else {
-                over_2b += 1;
-            }
-
-            encoder.write(&[shared_len as u8]).unwrap();
-            encoder.write(&[suffix.len() as u8]).unwrap();
-            encoder.write(suffix).unwrap();
-            let vlen = ss.serialize(&mut encoder).unwrap();
-
-            let fixed_ovh = 2 + vlen;
-            let entry_size = suffix.len() + fixed_ovh;
-            global_binary_offset += ser_size as u32;
-            l2_raw_size += entry_size as u32;
-
-            prev_word = current_word;
+            std::process::exit(1);
         }
+    }
+}
 
-        // L1 size ~ 71KB (stays in memory, need to read entirely)
-        // L2 size 5~10MB (seek, useful to not store entire word ever)
-        level1_data.extend(l1_group);
-        level1_data.extend(l2_raw_size.to_le_bytes());
-        level1_data.extend(group_binary_start.to_le_bytes());
-        assert!(level1_data.len() % 11 == 0);
-        // each entry == 11 bytes, assert this in a better way
-        // l1_group == 3
-        // l2_raw_size == 4 bytes
-        // group_binary_start == 4 bytes
-
-        level2_size += l2_raw_size as u32;
+/// Ranked words of a frequency list: one per line, optionally followed by
+/// whitespace and anything else, like a count
+fn read_frequency_list(path: &Path) -> std::io::Result<Vec<String>> {
+    let mut words = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        if let Some(word) = line?.split_whitespace().next() {
+            words.push(word.to_string());
+        }
     }
-    println!("ser size: under1 {under_1b} under2 {under_2b} over2 {over_2b}");
-    println!("saved {shared_prefixes}b with prefix thing");
-    println!("serialized size = {}b", all_serialized.len());
-    println!("compressed {:?}", s.elapsed());
-
-    let mut total_ser_size = 0u32;
-    encoder.write_all(&all_serialized).unwrap();
-    total_ser_size += all_serialized.len() as u32;
-    encoder.finish().unwrap();
-    println!("finish compress {:?}", s.elapsed());
-
-    w.write_all(b"DICT").unwrap();
-    w.write_all(&(level1_data.len() as u32).to_le_bytes())
-        .unwrap();
-    w.write_all(&(level2_size as u32).to_le_bytes()).unwrap();
-    w.write_all(&word_count.to_le_bytes()).unwrap();
-    // ^16
-    w.write_all(&timestamp_s.to_le_bytes()).unwrap();
-    // ^24
-    w.write_all(&[TARKKA_FMT_VERSION]).unwrap();
-    // ^25
-    // reserved 7 bytes
-    w.write_all(&[0, 0, 0]).unwrap(); // 28
-    w.write_all(&0u32.to_le_bytes()).unwrap(); // 32
-    w.write_all(&level1_data).unwrap();
-    w.write_all(&output).unwrap();
-    w.flush().unwrap();
-
-    // output = compressed, level2_size = raw
-    let compressed_ser_sz = output.len() - level2_size as usize;
-
-    println!("Created dictionary.dict with {} words", sorted_words.len());
-    println!("Header size (static) {}", HEADER_SIZE);
-    println!("Level 1 starts at {}", HEADER_SIZE);
-    println!("Level 1 size: {} bytes", level1_data.len());
-    println!(
-        "Level 2 starts at {}",
-        HEADER_SIZE as usize + level1_data.len()
-    );
-    println!("Level 2 size: {} bytes", level2_size);
-    println!(
-        "Level 2 ends at bytes {}",
-        HEADER_SIZE as u32 + level1_data.len() as u32 + level2_size
-    );
-    println!(
-        "data size: raw {} compressed {}",
-        total_ser_size, compressed_ser_sz
-    );
-    word_count
+    Ok(words)
 }
 
 // TODO: a bit garbo to do this
@@ -382,433 +571,14 @@ fn filter<R: Read + Seek>(raw_data: R) -> Vec<KaikkiWordEntry> {
     let mut words: Vec<KaikkiWordEntry> = Vec::with_capacity(1_000_000);
 
     for line in lines {
-        let mut kaikki_word: KaikkiWordEntry = serde_json::from_str(&line.unwrap()).unwrap();
-        kaikki_word.sounds.retain_mut(|s| s.ipa.is_some());
-        words.push(kaikki_word);
+        words.push(parse_entry(&line.unwrap()).unwrap());
     }
     words
 }
 
-// multiple entries with a single sense into 1 entry with multiple senses
-fn aggregate_entries(entries: Vec<WordWithTaggedEntries>) -> WordEntryComplete {
-    if entries.is_empty() {
-        panic!("Cannot aggregate empty entries");
-    }
-
-    if entries.len() == 1 {
-        let entry_data = entries.into_iter().next().unwrap();
-        let mut word_entry = entry_data.entries.into_iter().next().unwrap_or_else(|| {
-            panic!("WordWithTaggedEntries must have at least one entry");
-        });
-        // Still need to compress categories and merge senses even for single entry
-        merge_same_pos_senses(&mut word_entry.senses);
-        return word_entry;
-    }
-
-    // Take the first entry as base and aggregate others into it
-    let mut base = entries[0].entries[0].clone();
-
-    for entry_data in entries.into_iter() {
-        for word_entry in entry_data.entries {
-            // Aggregate senses with POS preserved in each sense
-            for sense in word_entry.senses {
-                base.senses.push(sense);
-            }
-        }
-    }
-
-    // Merge senses with the same POS and compress categories after aggregation
-    merge_same_pos_senses(&mut base.senses);
-    base
-}
-
-fn merge_same_pos_senses(senses: &mut Vec<tarkka::Sense>) {
-    use std::collections::HashMap;
-
-    let mut pos_to_sense: HashMap<PartOfSpeech, tarkka::Sense> = HashMap::new();
-
-    for sense in senses.drain(..) {
-        // remove SoftRedirects here, they are already part of redirects
-        if sense.pos == PartOfSpeech::SoftRedirect {
-            continue;
-        }
-        match pos_to_sense.get_mut(&sense.pos) {
-            Some(existing_sense) => {
-                // Merge glosses
-                existing_sense.glosses.extend(sense.glosses);
-                existing_sense.glosses = existing_sense.glosses.iter().cloned().unique().collect();
-            }
-            None => {
-                pos_to_sense.insert(sense.pos, sense);
-            }
-        }
-    }
-
-    // Convert back to Vec and sort by POS for consistent ordering
-    let mut merged_senses: Vec<tarkka::Sense> = pos_to_sense
-        .into_values()
-        .filter(|e| !e.glosses.is_empty())
-        .filter(|e| !(e.glosses.iter().all(|g| g.gloss_lines.is_empty())))
-        .collect();
-    merged_senses.sort_by(|a, b| a.pos.to_string().cmp(&b.pos.to_string()));
-    *senses = merged_senses;
-}
-
-pub fn build_tagged_index(
-    monolingual_entries: Vec<WordWithTaggedEntries>,
-    english_entries: Vec<WordWithTaggedEntries>,
-) -> Vec<WordWithTaggedEntries> {
-    let mut word_groups: HashMap<String, (Vec<WordWithTaggedEntries>, Vec<WordWithTaggedEntries>)> =
-        HashMap::new();
-
-    // Populate monolingual entries
-    for entry in monolingual_entries {
-        let word_str = entry.word.clone();
-        word_groups
-            .entry(word_str)
-            .or_insert((Vec::new(), Vec::new()))
-            .0
-            .push(entry);
-    }
-
-    // Populate English entries
-    for entry in english_entries {
-        let word_str = entry.word.clone();
-        word_groups
-            .entry(word_str)
-            .or_insert((Vec::new(), Vec::new()))
-            .1
-            .push(entry);
-    }
-
-    // Helper to extract sounds and hyphenations from entries
-    let extract_sound_and_hyph =
-        |entries: &[WordWithTaggedEntries]| -> (Option<String>, Vec<String>) {
-            let selected_sound = entries.iter().find_map(|e| e.sounds.clone());
-            let selected_hyphenation = entries
-                .iter()
-                .find(|e| !e.hyphenations.is_empty())
-                .map(|e| e.hyphenations.clone())
-                .unwrap_or_default();
-            (selected_sound, selected_hyphenation)
-        };
-
-    // Get all words in alphabetical order
-    let mut words: Vec<String> = word_groups.keys().cloned().collect();
-    words.sort();
-
-    let mut result: Vec<WordWithTaggedEntries> = Vec::new();
-
-    for word in words {
-        let (mono_entries, eng_entries) = word_groups.remove(&word).unwrap();
-        let tag = match (mono_entries.is_empty(), eng_entries.is_empty()) {
-            (false, true) => WordTag::Monolingual,
-            (true, false) => WordTag::English,
-            (false, false) => WordTag::Both,
-            (true, true) => unreachable!("Empty word group"),
-        };
-
-        let (entries, selected_sound, selected_hyphenation, redirects) = match tag {
-            WordTag::Monolingual => {
-                let (sound, hyph) = extract_sound_and_hyph(&mono_entries);
-                let redirects: Vec<String> = mono_entries
-                    .iter()
-                    .map(|e| e.redirects.clone())
-                    .flatten()
-                    .collect();
-                let entry = aggregate_entries(mono_entries);
-
-                (vec![entry], sound, hyph, redirects)
-            }
-            WordTag::English => {
-                let redirects: Vec<String> = eng_entries
-                    .iter()
-                    .map(|e| e.redirects.clone())
-                    .flatten()
-                    .collect();
-                let (sound, hyph) = extract_sound_and_hyph(&eng_entries);
-                let entry = aggregate_entries(eng_entries);
-                (vec![entry], sound, hyph, redirects)
-            }
-            WordTag::Both => {
-                let mut eng_redirects: Vec<_> = eng_entries
-                    .iter()
-                    .map(|e| e.redirects.clone())
-                    .flatten()
-                    .collect();
-                let mut redirects: Vec<String> = mono_entries
-                    .iter()
-                    .map(|e| e.redirects.clone())
-                    .flatten()
-                    .collect();
-
-                // Prefer monolingual sound/hyphenation, fallback to English
-                let (mono_sound, mono_hyph) = extract_sound_and_hyph(&mono_entries);
-                let (eng_sound, eng_hyph) = extract_sound_and_hyph(&eng_entries);
-
-                let eng_entry = aggregate_entries(eng_entries);
-                let mono_entry = aggregate_entries(mono_entries);
-
-                redirects.append(&mut eng_redirects);
-
-                let selected_sound = mono_sound.or(eng_sound);
-                let selected_hyphenation = if !mono_hyph.is_empty() {
-                    mono_hyph
-                } else {
-                    eng_hyph
-                };
-
-                (
-                    vec![mono_entry, eng_entry],
-                    selected_sound,
-                    selected_hyphenation,
-                    redirects,
-                )
-            }
-        };
-
-        result.push(WordWithTaggedEntries {
-            word,
-            tag,
-            entries,
-            sounds: selected_sound,
-            hyphenations: selected_hyphenation,
-            redirects: redirects.iter().cloned().unique().collect(),
-        });
-    }
-    result
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::reader::DictionaryReader;
-    use std::{
-        io::Cursor,
-        time::{SystemTime, UNIX_EPOCH},
-    };
-    use tarkka::{WordEntryComplete, WordTag};
-
-    fn create_test_word(
-        _word: &str,
-        pos: &str,
-        gloss: &str,
-    ) -> (
-        WordEntryComplete,
-        Vec<tarkka::kaikki::Sound>,
-        Vec<tarkka::kaikki::Hyphenation>,
-    ) {
-        (
-            WordEntryComplete {
-                senses: vec![tarkka::Sense {
-                    pos: PartOfSpeech::try_from(pos).expect("invalid pos in test"),
-                    glosses: vec![tarkka::Gloss {
-                        gloss_lines: vec![gloss.to_string()],
-                    }],
-                }],
-            },
-            vec![],
-            vec![],
-        )
-    }
-
-    #[test]
-    fn test_build_tagged_index() {
-        let test_words = vec![
-            {
-                let (entry, sounds, hyphenations) =
-                    create_test_word("dictate", "verb", "to say words aloud");
-                ("dictate".to_string(), entry, sounds, hyphenations, true)
-            },
-            {
-                let (entry, sounds, hyphenations) =
-                    create_test_word("dictionary", "noun", "a book of word definitions");
-                ("dictionary".to_string(), entry, sounds, hyphenations, true)
-            },
-            {
-                let (entry, sounds, hyphenations) =
-                    create_test_word("dictionary", "noun", "a reference book");
-                ("dictionary".to_string(), entry, sounds, hyphenations, false)
-            },
-            {
-                let (entry, sounds, hyphenations) =
-                    create_test_word("dictoto", "noun", "fictional word for testing");
-                ("dictoto".to_string(), entry, sounds, hyphenations, true)
-            },
-            {
-                let (entry, sounds, hyphenations) = create_test_word("pa", "noun", "short word");
-                ("pa".to_string(), entry, sounds, hyphenations, false)
-            },
-            {
-                let (entry, sounds, hyphenations) = create_test_word("papa", "noun", "father");
-                ("papa".to_string(), entry, sounds, hyphenations, true)
-            },
-            {
-                let (entry, sounds, hyphenations) = create_test_word("papo", "noun", "chat");
-                ("papo".to_string(), entry, sounds, hyphenations, true)
-            },
-            {
-                let (entry, sounds, hyphenations) =
-                    create_test_word("potato", "noun", "a vegetable");
-                ("potato".to_string(), entry, sounds, hyphenations, false)
-            },
-        ];
-
-        let result = build_tagged_index(test_words);
-
-        assert_eq!(result.len(), 7);
-
-        let words: Vec<&str> = result.iter().map(|w| w.word.as_str()).collect();
-        assert_eq!(
-            words,
-            vec![
-                "dictate",
-                "dictionary",
-                "dictoto",
-                "pa",
-                "papa",
-                "papo",
-                "potato"
-            ]
-        );
-
-        // Check tags
-        let dictionary = result.iter().find(|w| w.word == "dictionary").unwrap();
-        assert!(matches!(dictionary.tag, WordTag::Both));
-        assert_eq!(dictionary.entries.len(), 2); // Exactly 2 entries for Both tag
-
-        let dictate = result.iter().find(|w| w.word == "dictate").unwrap();
-        assert!(matches!(dictate.tag, WordTag::Monolingual));
-        assert_eq!(dictate.entries.len(), 1);
-
-        let pa = result.iter().find(|w| w.word == "pa").unwrap();
-        assert!(matches!(pa.tag, WordTag::English));
-        assert_eq!(pa.entries.len(), 1);
-    }
-
-    #[test]
-    fn test_tagged_write_read_roundtrip() {
-        let test_words = vec![
-            {
-                let (entry, sounds, hyphenations) =
-                    create_test_word("dictate", "verb", "to say words aloud");
-                ("dictate".to_string(), entry, sounds, hyphenations, true)
-            },
-            {
-                let (entry, sounds, hyphenations) =
-                    create_test_word("dictionary", "noun", "a book of word definitions");
-                ("dictionary".to_string(), entry, sounds, hyphenations, true)
-            },
-            {
-                let (entry, sounds, hyphenations) =
-                    create_test_word("dictionary", "noun", "reference book");
-                ("dictionary".to_string(), entry, sounds, hyphenations, false)
-            },
-            {
-                let (entry, sounds, hyphenations) =
-                    create_test_word("dictoto", "noun", "fictional word for testing");
-                ("dictoto".to_string(), entry, sounds, hyphenations, true)
-            },
-            {
-                let (entry, sounds, hyphenations) = create_test_word("pa", "noun", "short word");
-                ("pa".to_string(), entry, sounds, hyphenations, false)
-            },
-            {
-                let (entry, sounds, hyphenations) = create_test_word("papa", "noun", "father");
-                ("papa".to_string(), entry, sounds, hyphenations, true)
-            },
-            {
-                let (entry, sounds, hyphenations) = create_test_word("papo", "noun", "chat");
-                ("papo".to_string(), entry, sounds, hyphenations, true)
-            },
-            {
-                let (entry, sounds, hyphenations) =
-                    create_test_word("potato", "noun", "a vegetable");
-                ("potato".to_string(), entry, sounds, hyphenations, false)
-            },
-        ];
-
-        let tagged_words = build_tagged_index(test_words);
-
-        let mut buffer = Vec::new();
-        let now = SystemTime::now().duration_since(UNIX_EPOCH);
-        write_tagged(&mut buffer, tagged_words, now);
-
-        let cursor = Cursor::new(buffer);
-        let mut dict_reader = DictionaryReader::open(cursor).unwrap();
-
-        let result = dict_reader.lookup("dictionary").unwrap();
-        assert!(result.is_some());
-        let word = result.unwrap();
-        assert_eq!(word.word, "dictionary");
-        assert!(matches!(word.tag, WordTag::Both));
-        assert_eq!(word.entries.len(), 2); // Exactly 2 entries for Both tag
-        assert_eq!(word.entries[0].senses[0].pos, PartOfSpeech::Noun); // First entry is monolingual
-        assert_eq!(
-            word.entries[0].senses[0].glosses[0].gloss_lines[0],
-            "a book of word definitions"
-        );
-        assert_eq!(word.entries[1].senses[0].pos, PartOfSpeech::Noun); // Second entry is English
-        assert_eq!(
-            word.entries[1].senses[0].glosses[0].gloss_lines[0],
-            "reference book"
-        );
-
-        let result = dict_reader.lookup("papa").unwrap();
-        assert!(result.is_some());
-        let word = result.unwrap();
-        assert_eq!(word.word, "papa");
-        assert!(matches!(word.tag, WordTag::Monolingual));
-        assert_eq!(word.entries.len(), 1);
-        assert_eq!(word.entries[0].senses[0].pos, PartOfSpeech::Noun);
-
-        let result = dict_reader.lookup("nonexistent").unwrap();
-        assert!(result.is_none());
-    }
-
-    #[test]
-    fn test_merge_same_pos_senses() {
-        let mut entry = WordEntryComplete {
-            senses: vec![
-                tarkka::Sense {
-                    pos: PartOfSpeech::Noun,
-                    glosses: vec![tarkka::Gloss {
-                        gloss_lines: vec!["first noun definition".to_string()],
-                    }],
-                },
-                tarkka::Sense {
-                    pos: PartOfSpeech::Adj,
-                    glosses: vec![tarkka::Gloss {
-                        gloss_lines: vec!["adjective definition".to_string()],
-                    }],
-                },
-                tarkka::Sense {
-                    pos: PartOfSpeech::Noun,
-                    glosses: vec![tarkka::Gloss {
-                        gloss_lines: vec!["second noun definition".to_string()],
-                    }],
-                },
-            ],
-        };
-
-        merge_same_pos_senses(&mut entry.senses);
-
-        // Should now have only 2 senses: 1 adj and 1 noun (with 2 glosses)
-        assert_eq!(entry.senses.len(), 2);
-
-        // Find the noun sense (should be first due to sorting)
-        let noun_sense = entry
-            .senses
-            .iter()
-            .find(|s| s.pos == PartOfSpeech::Noun)
-            .unwrap();
-        assert_eq!(noun_sense.glosses.len(), 2);
-
-        let adj_sense = entry
-            .senses
-            .iter()
-            .find(|s| s.pos == PartOfSpeech::Adj)
-            .unwrap();
-        assert_eq!(adj_sense.glosses.len(), 1);
-    }
+/// A line of Kaikki's JSONL, without the sounds that have no IPA
+fn parse_entry(line: &str) -> serde_json::Result<KaikkiWordEntry> {
+    let mut kaikki_word: KaikkiWordEntry = serde_json::from_str(line)?;
+    kaikki_word.sounds.retain_mut(|s| s.ipa.is_some());
+    Ok(kaikki_word)
 }
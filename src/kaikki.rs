@@ -1,4 +1,7 @@
-use crate::{Gloss, PartOfSpeech, Sense, WordEntryComplete, WordTag, WordWithTaggedEntries};
+use crate::{
+    Gloss, GlossLine, LinkKind, PartOfSpeech, Sense, SenseLink, WordEntryComplete, WordTag,
+    WordWithTaggedEntries,
+};
 use itertools::Itertools;
 
 #[cfg(feature = "indexer")]
@@ -34,16 +37,72 @@ pub struct KaikkiWordEntry {
     pub sounds: Vec<Sound>,
     #[cfg_attr(feature = "indexer", serde(default))]
     pub redirects: Vec<String>,
+    #[cfg_attr(feature = "indexer", serde(default))]
+    pub forms: Vec<KaikkiForm>,
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "indexer", derive(Debug, Serialize, Deserialize))]
+pub struct KaikkiForm {
+    pub form: String,
+    #[cfg_attr(feature = "indexer", serde(default))]
+    pub tags: Vec<String>,
+}
+
+// Kaikki also lists inflection table headers and templates as forms
+const NON_FORM_TAGS: &[&str] = &[
+    "table-tags",
+    "inflection-template",
+    "class",
+    "canonical",
+    "romanization",
+];
+
 #[derive(Clone)]
 #[cfg_attr(feature = "indexer", derive(Debug, Serialize, Deserialize))]
 pub struct KaikkiSense {
     #[cfg_attr(feature = "indexer", serde(default))]
     pub glosses: Vec<String>,
+    #[cfg_attr(feature = "indexer", serde(default))]
+    pub form_of: Vec<KaikkiLink>,
+    #[cfg_attr(feature = "indexer", serde(default))]
+    pub alt_of: Vec<KaikkiLink>,
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "indexer", derive(Debug, Serialize, Deserialize))]
+pub struct KaikkiLink {
+    pub word: String,
+}
+
+impl KaikkiSense {
+    fn links(&self) -> Vec<SenseLink> {
+        let form_of = (self.form_of.iter()).map(|l| (LinkKind::FormOf, l));
+        let alt_of = (self.alt_of.iter()).map(|l| (LinkKind::AltOf, l));
+        form_of
+            .chain(alt_of)
+            .filter(|(_, l)| !l.word.trim().is_empty())
+            .map(|(kind, l)| SenseLink {
+                kind,
+                target: l.word.trim().to_string(),
+            })
+            .unique()
+            .collect()
+    }
 }
 
 impl KaikkiWordEntry {
+    /// Single-word inflected forms of this entry, as (form, tags)
+    pub fn inflected_forms(&self) -> Vec<(String, Vec<String>)> {
+        self.forms
+            .iter()
+            .filter(|f| !f.form.is_empty() && f.form != self.word && !f.form.contains(' '))
+            .filter(|f| !f.tags.iter().any(|t| NON_FORM_TAGS.contains(&t.as_str())))
+            .map(|f| (f.form.clone(), f.tags.clone()))
+            .unique_by(|(form, tags)| (form.clone(), tags.clone()))
+            .collect()
+    }
+
     pub fn to_word_entry_complete(self, tag: WordTag) -> WordWithTaggedEntries {
         let pos_str = self.pos.expect("pos is required");
         let pos = PartOfSpeech::try_from(pos_str.as_str()).expect("invalid part of speech");
@@ -52,6 +111,7 @@ impl KaikkiWordEntry {
             .into_iter()
             .map(|kaikki_sense| Sense {
                 pos,
+                links: kaikki_sense.links(),
                 glosses: [Gloss {
                     gloss_lines: kaikki_sense
                         .glosses
@@ -65,6 +125,7 @@ impl KaikkiWordEntry {
                                 .to_string()
                         })
                         .unique()
+                        .map(GlossLine::Inline)
                         .collect(),
                 }]
                 .iter()
@@ -88,6 +149,7 @@ impl KaikkiWordEntry {
         WordWithTaggedEntries {
             tag,
             word: self.word,
+            id: None,
             entries: vec![WordEntryComplete { senses }],
             sounds: self
                 .sounds
@@ -1,26 +1,425 @@
-use std::io::Read;
+use std::borrow::Cow;
+use std::io::{Read, Write};
+pub mod bloom;
+pub mod codec;
 pub mod de;
 pub mod kaikki;
+pub mod merge;
+pub mod mphf;
+pub mod patch;
 pub mod reader;
+pub mod section;
 pub mod ser;
+pub mod shard;
+pub mod signature;
+pub mod streaming;
+pub mod strings;
+pub mod writer;
+
+#[cfg(test)]
+mod test_util;
 use de::CompactDeserialize;
 use ser::CompactSerialize;
 
 use crate::de::DeserializeError;
+use crate::ser::SerializeError;
+use itertools::Itertools;
+use unicode_normalization::{UnicodeNormalization, is_nfc};
 
 pub const HEADER_SIZE: u8 = 32;
-pub const TARKKA_FMT_VERSION: u8 = 1;
+pub const TARKKA_FMT_VERSION: u8 = 3;
+
+/// Unicode normalization form applied to the keys of a dictionary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Normalization {
+    /// Keys are compared as given
+    None = 0,
+    Nfc = 1,
+}
+
+impl TryFrom<u8> for Normalization {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Normalization::None),
+            1 => Ok(Normalization::Nfc),
+            _ => Err(format!("Unknown normalization form: {}", value)),
+        }
+    }
+}
+
+impl Normalization {
+    pub fn apply<'a>(&self, word: &'a str) -> Cow<'a, str> {
+        match self {
+            Normalization::None => Cow::Borrowed(word),
+            Normalization::Nfc => nfc_key(word),
+        }
+    }
+}
+
+/// How the stream after the section directory is compressed, recorded in
+/// the header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum Codec {
+    /// Seekable zstd, see [`zeekstd`]
+    #[default]
+    Zstd = 0,
+    /// Independent LZ4 blocks, followed by a block table. Faster to
+    /// decompress than zstd, but bigger.
+    Lz4 = 1,
+    /// Uncompressed
+    Stored = 2,
+}
+
+impl std::str::FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zstd" => Ok(Codec::Zstd),
+            "lz4" => Ok(Codec::Lz4),
+            "stored" => Ok(Codec::Stored),
+            _ => Err(format!("Unknown codec: {}", s)),
+        }
+    }
+}
+
+impl TryFrom<u8> for Codec {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Codec::Zstd),
+            1 => Ok(Codec::Lz4),
+            2 => Ok(Codec::Stored),
+            _ => Err(format!("Unknown codec: {}", value)),
+        }
+    }
+}
+
+/// Where the binary data of each Level 2 group goes in the stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum StreamLayout {
+    /// All the Level 2 groups, then all the binary data
+    Split = 0,
+    /// Each group's Level 2 entries, directly followed by its binary data,
+    /// with frames cut between groups: a cold lookup reads a single frame
+    Interleaved = 1,
+}
+
+impl TryFrom<u8> for StreamLayout {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(StreamLayout::Split),
+            1 => Ok(StreamLayout::Interleaved),
+            _ => Err(format!("Unknown stream layout: {}", value)),
+        }
+    }
+}
+
+/// Encoding of the offsets and sizes in Level 1 and the forms section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Offsets {
+    /// 32-bit LE, the stream must be under 4 GiB
+    U32 = 0,
+    /// [`ser::VarU64`], for streams of any size
+    Varint = 1,
+}
+
+impl TryFrom<u8> for Offsets {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Offsets::U32),
+            1 => Ok(Offsets::Varint),
+            _ => Err(format!("Unknown offsets encoding: {}", value)),
+        }
+    }
+}
+
+impl Offsets {
+    /// Fails if `value` doesn't fit, rather than writing a wrapped offset
+    pub fn write<W: std::io::Write>(&self, out: &mut W, value: u64) -> Result<usize, String> {
+        let written = match self {
+            Offsets::U32 => u32::try_from(value)
+                .map_err(|_| format!("Offset {} doesn't fit in 32 bits", value))?
+                .serialize(out),
+            Offsets::Varint => ser::VarU64(value).serialize(out),
+        };
+        written.map_err(|e| format!("{:?}", e))
+    }
+
+    pub fn read<R: Read>(&self, input: &mut R) -> Result<u64, DeserializeError> {
+        match self {
+            Offsets::U32 => Ok(u32::deserialize(input)? as u64),
+            Offsets::Varint => Ok(de::VarU64::deserialize(input)?.0),
+        }
+    }
+}
+
+/// `word` in NFC, only allocating if it isn't already
+pub fn nfc_key(word: &str) -> Cow<'_, str> {
+    if is_nfc(word) {
+        Cow::Borrowed(word)
+    } else {
+        Cow::Owned(word.nfc().collect())
+    }
+}
+
+/// How keys are split into Level 2 groups, each with one Level 1 entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grouping {
+    /// One group per [`level1_key`], ie: per first 3 bytes
+    Prefix3,
+    /// Runs of consecutive keys (in byte order), starting a new group when
+    /// the current one would grow past `max_group_size` bytes of Level 2
+    /// data, or at some keys once it's past 3/4 of it, so edits don't move
+    /// every group after them. Each group is keyed by [`separator_key`].
+    Bounded { max_group_size: u32 },
+}
+
+impl Grouping {
+    /// Payload of the `GRUP` section: strategy (1 B) + max group size (4 B LE)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (id, max_group_size) = match self {
+            Grouping::Prefix3 => (0u8, 0u32),
+            Grouping::Bounded { max_group_size } => (1u8, *max_group_size),
+        };
+        let mut b = vec![id];
+        b.extend(max_group_size.to_le_bytes());
+        b
+    }
+
+    pub fn from_bytes(b: &[u8]) -> Result<Self, String> {
+        if b.len() < 5 {
+            return Err("Truncated grouping section".to_string());
+        }
+        let max_group_size = u32::from_le_bytes([b[1], b[2], b[3], b[4]]);
+        match b[0] {
+            0 => Ok(Grouping::Prefix3),
+            1 => Ok(Grouping::Bounded { max_group_size }),
+            x => Err(format!("Unknown grouping strategy: {}", x)),
+        }
+    }
+}
+
+/// Shortest prefix of `word` that ends on a char boundary and sorts after
+/// `prev`. `prev` must sort before `word`.
+pub fn separator_key<'a>(prev: &str, word: &'a str) -> &'a str {
+    let common = prev
+        .bytes()
+        .zip(word.bytes())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let end = word
+        .char_indices()
+        .map(|(i, c)| i + c.len_utf8())
+        .find(|&end| end > common)
+        .unwrap_or(word.len());
+    &word[..end]
+}
+
+/// When the compressed stream starts a new seekable frame. A lookup
+/// decompresses whole frames, so smaller frames mean faster lookups but a
+/// bigger file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameSize {
+    /// After this many uncompressed bytes
+    Uncompressed(u32),
+    /// Once the frame reaches this many compressed bytes
+    Compressed(u32),
+}
+
+/// zstd settings for one region of the compressed stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionCompression {
+    pub level: i32,
+    pub frame_size: FrameSize,
+}
+
+impl RegionCompression {
+    // level (4 B LE) | frame size kind (1 B) | frame size (4 B LE)
+    const SIZE: usize = 9;
+
+    fn write_bytes(&self, b: &mut Vec<u8>) {
+        let (kind, size) = match self.frame_size {
+            FrameSize::Uncompressed(size) => (0u8, size),
+            FrameSize::Compressed(size) => (1u8, size),
+        };
+        b.extend(self.level.to_le_bytes());
+        b.push(kind);
+        b.extend(size.to_le_bytes());
+    }
+
+    fn from_bytes(b: &[u8]) -> Result<Self, String> {
+        let level = i32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        let size = u32::from_le_bytes([b[5], b[6], b[7], b[8]]);
+        let frame_size = match b[4] {
+            0 => FrameSize::Uncompressed(size),
+            1 => FrameSize::Compressed(size),
+            x => return Err(format!("Unknown frame size policy: {}", x)),
+        };
+        Ok(RegionCompression { level, frame_size })
+    }
+}
+
+/// Compression of the Level 2 groups and of the binary data, which are
+/// tuned separately: every lookup reads one Level 2 group, then one entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    pub level2: RegionCompression,
+    pub data: RegionCompression,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        let region = RegionCompression {
+            level: 9,
+            frame_size: FrameSize::Uncompressed(1024 * 1024),
+        };
+        Compression {
+            level2: region,
+            data: region,
+        }
+    }
+}
+
+impl Compression {
+    /// Payload of the `CMPR` section: Level 2 settings, then data settings
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut b = Vec::with_capacity(2 * RegionCompression::SIZE);
+        self.level2.write_bytes(&mut b);
+        self.data.write_bytes(&mut b);
+        b
+    }
+
+    pub fn from_bytes(b: &[u8]) -> Result<Self, String> {
+        if b.len() < 2 * RegionCompression::SIZE {
+            return Err("Truncated compression section".to_string());
+        }
+        Ok(Compression {
+            level2: RegionCompression::from_bytes(&b[..RegionCompression::SIZE])?,
+            data: RegionCompression::from_bytes(&b[RegionCompression::SIZE..])?,
+        })
+    }
+}
+
+/// Level 1 group key for a word: its first 3 bytes, left-padded with zeroes
+/// for shorter words. `word` must not be empty.
+pub fn level1_key(word: &[u8]) -> [u8; 3] {
+    match word.len() {
+        0 => panic!("got empty word"),
+        1 => [0, 0, word[0]],
+        2 => [0, word[0], word[1]],
+        _ => [word[0], word[1], word[2]],
+    }
+}
 
 #[derive(Debug, Clone, CompactDeserialize, CompactSerialize)]
 pub struct WordEntryComplete {
-    #[max_len_cat(OneByte)]
+    #[max_len_cat(Var)]
     pub senses: Vec<Sense>,
 }
 
-#[derive(Debug, Clone, CompactDeserialize, CompactSerialize, Hash, PartialEq, Eq)]
+#[derive(
+    Debug, Clone, CompactDeserialize, CompactSerialize, Hash, PartialEq, Eq, PartialOrd, Ord,
+)]
 pub struct Gloss {
-    #[max_len_cat(OneByte)]
-    pub gloss_lines: Vec<String>,
+    #[max_len_cat(Var)]
+    pub gloss_lines: Vec<GlossLine>,
+}
+
+/// A gloss line as stored in an entry: its text, or the id of a line in the
+/// dictionary's string table (see `StringTable`). Readers resolve the ids,
+/// so a looked up word only has `Inline` lines.
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GlossLine {
+    Inline(String),
+    Ref(u32),
+}
+
+impl GlossLine {
+    /// The line's text, `None` for a string table reference
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            GlossLine::Inline(text) => Some(text),
+            GlossLine::Ref(_) => None,
+        }
+    }
+}
+
+impl From<String> for GlossLine {
+    fn from(text: String) -> Self {
+        GlossLine::Inline(text)
+    }
+}
+
+impl From<&str> for GlossLine {
+    fn from(text: &str) -> Self {
+        GlossLine::Inline(text.to_string())
+    }
+}
+
+impl PartialEq<str> for GlossLine {
+    fn eq(&self, other: &str) -> bool {
+        self.text() == Some(other)
+    }
+}
+
+impl PartialEq<&str> for GlossLine {
+    fn eq(&self, other: &&str) -> bool {
+        self.text() == Some(*other)
+    }
+}
+
+impl PartialEq<String> for GlossLine {
+    fn eq(&self, other: &String) -> bool {
+        self.text() == Some(other.as_str())
+    }
+}
+
+/// A LEB128 header, the low bit telling the kind: `len << 1` followed by the
+/// UTF-8 text, or `id << 1 | 1`
+impl CompactSerialize for GlossLine {
+    fn serialize<W: Write>(&self, out: &mut W) -> Result<usize, SerializeError> {
+        match self {
+            GlossLine::Inline(text) => {
+                let len = ser::VarU64((text.len() as u64) << 1).serialize(out)?;
+                out.write_all(text.as_bytes())?;
+                Ok(len + text.len())
+            }
+            GlossLine::Ref(id) => ser::VarU64((*id as u64) << 1 | 1).serialize(out),
+        }
+    }
+}
+
+impl CompactDeserialize for GlossLine {
+    fn deserialize<R: Read>(input: &mut R) -> Result<Self, DeserializeError> {
+        let header = de::VarU64::deserialize(input)?.0;
+        if header & 1 == 1 {
+            let id = u32::try_from(header >> 1)
+                .map_err(|_| DeserializeError::InvalidData("String table id too large"))?;
+            return Ok(GlossLine::Ref(id));
+        }
+        // Don't trust the length for the allocation, the data may be corrupt
+        let len = header >> 1;
+        let mut buf = Vec::new();
+        input.take(len).read_to_end(&mut buf)?;
+        if buf.len() as u64 != len {
+            return Err(DeserializeError::InvalidData("Truncated gloss line"));
+        }
+        String::from_utf8(buf)
+            .map(GlossLine::Inline)
+            .map_err(|_| DeserializeError::InvalidData("Invalid UTF-8 string"))
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, CompactDeserialize, CompactSerialize)]
@@ -197,10 +596,81 @@ impl std::fmt::Display for PartOfSpeech {
 #[derive(Debug, Clone, CompactDeserialize, CompactSerialize)]
 pub struct Sense {
     pub pos: PartOfSpeech,
-    #[max_len_cat(OneByte)]
+    #[max_len_cat(Var)]
     pub glosses: Vec<Gloss>,
+    /// Words this sense is a form or variant of, eg: `perros` -> `perro`
+    #[max_len_cat(Var)]
+    pub links: Vec<SenseLink>,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, CompactDeserialize, CompactSerialize,
+)]
+#[repr(u8)]
+pub enum LinkKind {
+    /// Inflection of the target, from Kaikki's `form_of`
+    FormOf = 1,
+    /// Alternative spelling or variant of the target, from Kaikki's `alt_of`
+    AltOf = 2,
+}
+
+/// A link from a sense to the word it's defined in terms of, so the sense
+/// can be rendered as "plural of [perro]" with the lemma's definition
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, CompactDeserialize, CompactSerialize,
+)]
+pub struct SenseLink {
+    pub kind: LinkKind,
+    pub target: String,
+}
+
+impl Sense {
+    /// Hash of the sense's word (NFC), the language of its entry and its
+    /// POS, which its id and its glosses' ids are derived from
+    pub fn key(&self, word: &str, tag: WordTag) -> u64 {
+        let mut key = nfc_key(word).into_owned().into_bytes();
+        key.extend([0, tag as u8, self.pos as u8]);
+        mphf::hash64(&key, SENSE_ID_SEED)
+    }
+
+    /// Deterministic id of the sense, from its [`key`](Self::key) and the
+    /// lines of its glosses, so senses of the same POS only share an id if
+    /// their glosses are the same. It changes when any of its glosses does,
+    /// while the ids of the others stay (see [`Gloss::id`]). Not stored.
+    pub fn id(&self, word: &str, tag: WordTag) -> u64 {
+        let mut key = self.key(word, tag).to_le_bytes().to_vec();
+        for gloss in &self.glosses {
+            for line in &gloss.gloss_lines {
+                key.extend(line.text().unwrap_or_default().as_bytes());
+                key.push(0);
+            }
+            // Never part of UTF-8, so it can't be confused with a line
+            key.push(0xff);
+        }
+        mphf::hash64(&key, SENSE_ID_SEED)
+    }
+}
+
+impl Gloss {
+    /// Deterministic id of the gloss, from its sense's
+    /// [`key`](Sense::key) and its lines, so it stays the same across
+    /// rebuilds while the gloss is unchanged. Not stored, and only
+    /// meaningful once string table references are resolved, as they are
+    /// in looked up words.
+    pub fn id(&self, sense_key: u64) -> u64 {
+        let mut key = sense_key.to_le_bytes().to_vec();
+        for line in &self.gloss_lines {
+            key.extend(line.text().unwrap_or_default().as_bytes());
+            key.push(0);
+        }
+        mphf::hash64(&key, SENSE_ID_SEED)
+    }
 }
 
+/// Hash seed of sense and gloss ids. Changing it, or `hash64`, changes
+/// every id users may have stored.
+const SENSE_ID_SEED: u64 = 0x5e45e1d5;
+
 #[derive(Debug, Clone, Copy, CompactDeserialize, CompactSerialize)]
 #[repr(u8)]
 pub enum WordTag {
@@ -209,26 +679,106 @@ pub enum WordTag {
     Both = 3,
 }
 
-#[derive(Debug, CompactSerialize, CompactDeserialize)]
+#[derive(Debug, Clone, CompactSerialize, CompactDeserialize)]
 pub struct WordWithTaggedEntries {
     pub tag: WordTag,
     #[skip]
     pub word: String,
+    /// The word's ordinal in the dictionary it was read from, if the file
+    /// records them, see `DictionaryReader::lookup_by_id`
+    #[skip]
+    pub id: Option<u32>,
     #[max_len_cat(OneByte)]
     pub entries: Vec<WordEntryComplete>,
     pub sounds: Option<String>,
-    #[max_len_cat(OneByte)]
+    #[max_len_cat(Var)]
     pub hyphenations: Vec<String>,
-    #[max_len_cat(OneByte)]
+    #[max_len_cat(Var)]
     pub redirects: Vec<String>,
 }
 
+/// A lemma that an inflected form belongs to, eg: `perros` -> `perro` with
+/// tags `["plural"]`
+#[derive(Debug, Clone, PartialEq, Eq, CompactDeserialize, CompactSerialize)]
+pub struct FormOf {
+    pub lemma: String,
+    #[max_len_cat(OneByte)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, CompactDeserialize, CompactSerialize)]
+pub struct FormEntry {
+    #[max_len_cat(OneByte)]
+    pub lemmas: Vec<FormOf>,
+}
+
 impl WordWithTaggedEntries {
+    pub fn gloss_lines(&self) -> impl Iterator<Item = &GlossLine> {
+        (self.entries.iter())
+            .flat_map(|e| &e.senses)
+            .flat_map(|s| &s.glosses)
+            .flat_map(|g| &g.gloss_lines)
+    }
+
+    pub fn gloss_lines_mut(&mut self) -> impl Iterator<Item = &mut GlossLine> {
+        (self.entries.iter_mut())
+            .flat_map(|e| &mut e.senses)
+            .flat_map(|s| &mut s.glosses)
+            .flat_map(|g| &mut g.gloss_lines)
+    }
+
+    /// Each entry with the language of its glosses: for `Both`, the
+    /// monolingual entry comes first, then the English one
+    pub fn tagged_entries(&self) -> impl Iterator<Item = (WordTag, &WordEntryComplete)> {
+        let tags: &[WordTag] = match self.tag {
+            WordTag::Monolingual => &[WordTag::Monolingual],
+            WordTag::English => &[WordTag::English],
+            WordTag::Both => &[WordTag::Monolingual, WordTag::English],
+        };
+        tags.iter().copied().zip(&self.entries)
+    }
+
+    /// The word's senses with their ids, see [`Sense::id`]
+    pub fn senses_with_ids(&self) -> impl Iterator<Item = (u64, &Sense)> {
+        self.tagged_senses()
+            .map(|(tag, s)| (s.id(&self.word, tag), s))
+    }
+
+    fn tagged_senses(&self) -> impl Iterator<Item = (WordTag, &Sense)> {
+        (self.tagged_entries()).flat_map(|(tag, e)| e.senses.iter().map(move |s| (tag, s)))
+    }
+
+    /// The sense with id `id`. With a gloss id (see [`Gloss::id`]), the
+    /// sense holds just that gloss.
+    pub fn sense_by_id(&self, id: u64) -> Option<Sense> {
+        self.tagged_senses().find_map(|(tag, sense)| {
+            if sense.id(&self.word, tag) == id {
+                return Some(sense.clone());
+            }
+            let sense_key = sense.key(&self.word, tag);
+            let gloss = sense.glosses.iter().find(|g| g.id(sense_key) == id)?;
+            Some(Sense {
+                glosses: vec![gloss.clone()],
+                ..sense.clone()
+            })
+        })
+    }
+
+    /// Distinct targets of the senses' links, in order
+    pub fn link_targets(&self) -> Vec<&str> {
+        (self.entries.iter())
+            .flat_map(|e| &e.senses)
+            .flat_map(|s| &s.links)
+            .map(|l| l.target.as_str())
+            .unique()
+            .collect()
+    }
+
     pub fn named_deserialize<R: Read>(
         data: &mut R,
         word: String,
     ) -> Result<Self, DeserializeError> {
-        let mut w = Self::deserialize(data).expect("failed to deserialze");
+        let mut w = Self::deserialize(data)?;
         w.word = word;
         Ok(w)
     }
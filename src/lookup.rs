@@ -1,6 +1,6 @@
 use std::{fs::File, io::BufReader, time::Instant};
 
-use tarkka::{PartOfSpeech, WordTag, WordWithTaggedEntries, reader::DictionaryReader};
+use tarkka::{GlossLine, PartOfSpeech, WordTag, WordWithTaggedEntries, reader::DictionaryReader};
 
 fn display_glosses_with_categories(
     glosses: &[tarkka::Gloss],
@@ -49,7 +49,12 @@ fn display_glosses_with_categories(
 
         // Print the gloss with appropriate indentation
         let indent = "  ".repeat(current_category_path.len() + 1);
-        println!("{}• {:?}", indent, gloss.gloss_lines);
+        let lines: Vec<&str> = gloss
+            .gloss_lines
+            .iter()
+            .filter_map(GlossLine::text)
+            .collect();
+        println!("{}• {:?}", indent, lines);
 
         last_category_path = current_category_path;
     }
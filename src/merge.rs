@@ -0,0 +1,353 @@
+//! Merging several `.dict` files into one, eg: a small glossary into a base
+//! dictionary, see [`merge_dictionaries`].
+
+use crate::reader::DictionaryReader;
+use crate::writer::{BuildReport, DictionaryWriter, merge_same_pos_senses, push_form_of};
+use crate::{FormEntry, FormOf, Sense, WordEntryComplete, WordTag, WordWithTaggedEntries, nfc_key};
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::io::{Read, Seek, Write};
+
+/// How the entries of a word found in several dictionaries are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// The word's entry from the first dictionary that has it
+    PreferFirst,
+    /// Senses of every dictionary, merged by POS within each language the
+    /// way the indexer merges a word's entries: glosses in the order of
+    /// `dictionaries`, repeats dropped, senses sorted by POS name
+    UnionSenses,
+    /// Senses of every dictionary one after another within each language,
+    /// as they are. A word holds one entry per language, so the inputs are
+    /// kept apart by sense rather than by entry. A sense with the same POS
+    /// and glosses as one already kept would have the same
+    /// [`id`](crate::Sense::id), so it only adds its links to it.
+    KeepAll,
+}
+
+impl std::str::FromStr for MergePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(MergePolicy::PreferFirst),
+            "union" => Ok(MergePolicy::UnionSenses),
+            "all" => Ok(MergePolicy::KeepAll),
+            _ => Err(format!("Unknown merge policy: {}", s)),
+        }
+    }
+}
+
+/// Writes the words and forms of all `dictionaries` as a single `.dict`,
+/// combining the words found in several of them with `policy`, in the order
+/// of `dictionaries`. Returns the merged dictionary's sizes.
+pub fn merge_dictionaries<R: Read + Seek, W: Write>(
+    dictionaries: &mut [DictionaryReader<'_, R>],
+    policy: MergePolicy,
+    writer: &DictionaryWriter,
+    w: W,
+) -> Result<BuildReport, Box<dyn std::error::Error>> {
+    let mut words: BTreeMap<String, WordWithTaggedEntries> = BTreeMap::new();
+    let mut forms: BTreeMap<String, Vec<FormOf>> = BTreeMap::new();
+    for dictionary in dictionaries.iter_mut() {
+        for mut word in dictionary.words()? {
+            // Ids are ordinals of the source dictionary
+            word.id = None;
+            word.word = nfc_key(&word.word).into_owned();
+            match words.entry(word.word.clone()) {
+                Entry::Vacant(e) => {
+                    e.insert(word);
+                }
+                Entry::Occupied(mut e) => merge_word(e.get_mut(), word, policy),
+            }
+        }
+        for (form, entry) in dictionary.forms()? {
+            let merged = forms.entry(nfc_key(&form).into_owned()).or_default();
+            for form_of in entry.lemmas {
+                push_form_of(merged, form_of);
+            }
+        }
+    }
+
+    let forms: Vec<(String, FormEntry)> = (forms.into_iter())
+        .map(|(form, lemmas)| (form, FormEntry { lemmas }))
+        .collect();
+    Ok(writer.write(w, words.values(), &forms)?)
+}
+
+/// Adds `other`'s entries to `word`'s, see [`MergePolicy`]
+fn merge_word(word: &mut WordWithTaggedEntries, other: WordWithTaggedEntries, policy: MergePolicy) {
+    if policy == MergePolicy::PreferFirst {
+        return;
+    }
+    let [mono, english] = by_language(std::mem::take(&mut word.entries), word.tag);
+    let [other_mono, other_english] = by_language(other.entries, other.tag);
+    let merge = |a: Option<WordEntryComplete>, b: Option<WordEntryComplete>| match (a, b) {
+        (Some(a), Some(b)) => Some(merge_entries(a, b, policy)),
+        (a, b) => a.or(b),
+    };
+    let (tag, entries) = match (merge(mono, other_mono), merge(english, other_english)) {
+        (Some(mono), None) => (WordTag::Monolingual, vec![mono]),
+        (None, Some(english)) => (WordTag::English, vec![english]),
+        (Some(mono), Some(english)) => (WordTag::Both, vec![mono, english]),
+        (None, None) => (word.tag, vec![]),
+    };
+    word.tag = tag;
+    word.entries = entries;
+
+    if word.sounds.is_none() {
+        word.sounds = other.sounds;
+    }
+    if word.hyphenations.is_empty() {
+        word.hyphenations = other.hyphenations;
+    }
+    for redirect in other.redirects {
+        if !word.redirects.contains(&redirect) {
+            word.redirects.push(redirect);
+        }
+    }
+}
+
+/// `[monolingual, English]` entries, see
+/// [`WordWithTaggedEntries::tagged_entries`]
+fn by_language(entries: Vec<WordEntryComplete>, tag: WordTag) -> [Option<WordEntryComplete>; 2] {
+    let mut entries = entries.into_iter();
+    match tag {
+        WordTag::Monolingual => [entries.next(), None],
+        WordTag::English => [None, entries.next()],
+        WordTag::Both => [entries.next(), entries.next()],
+    }
+}
+
+fn merge_entries(
+    mut entry: WordEntryComplete,
+    other: WordEntryComplete,
+    policy: MergePolicy,
+) -> WordEntryComplete {
+    match policy {
+        MergePolicy::PreferFirst => {}
+        MergePolicy::UnionSenses => {
+            entry.senses.extend(other.senses);
+            merge_same_pos_senses(&mut entry.senses);
+        }
+        MergePolicy::KeepAll => {
+            for sense in other.senses {
+                let same = |s: &&mut Sense| s.pos == sense.pos && s.glosses == sense.glosses;
+                let Some(same) = entry.senses.iter_mut().find(same) else {
+                    entry.senses.push(sense);
+                    continue;
+                };
+                for link in sense.links {
+                    if !same.links.contains(&link) {
+                        same.links.push(link);
+                    }
+                }
+            }
+        }
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{plural_forms, tagged_words, write_dict};
+    use crate::writer::WriterOptions;
+    use crate::{Codec, Gloss, LinkKind, Offsets, PartOfSpeech, SenseLink, StreamLayout};
+    use std::collections::HashSet;
+    use std::io::Cursor;
+
+    fn sense(pos: PartOfSpeech, glosses: &[&str], links: &[&str]) -> Sense {
+        Sense {
+            pos,
+            glosses: (glosses.iter())
+                .map(|g| Gloss {
+                    gloss_lines: vec![(*g).into()],
+                })
+                .collect(),
+            links: (links.iter())
+                .map(|l| SenseLink {
+                    kind: LinkKind::AltOf,
+                    target: l.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    fn word(tag: WordTag, entries: Vec<Vec<Sense>>) -> WordWithTaggedEntries {
+        WordWithTaggedEntries {
+            tag,
+            word: "casa".to_string(),
+            id: None,
+            entries: (entries.into_iter())
+                .map(|senses| WordEntryComplete { senses })
+                .collect(),
+            sounds: None,
+            hyphenations: vec![],
+            redirects: vec![],
+        }
+    }
+
+    #[test]
+    fn test_merge_policies() {
+        use PartOfSpeech::{Noun, Verb};
+        let base = word(
+            WordTag::Monolingual,
+            vec![vec![sense(Noun, &["house"], &["kasa"])]],
+        );
+        let other = word(
+            WordTag::Both,
+            vec![
+                vec![
+                    sense(Verb, &["to marry"], &[]),
+                    sense(Noun, &["home", "house"], &[]),
+                    sense(Noun, &["house"], &["cassa", "kasa"]),
+                ],
+                vec![sense(Noun, &["house"], &[])],
+            ],
+        );
+
+        let mono = |senses: Vec<Sense>| word(WordTag::Monolingual, vec![senses]);
+        let both = |senses: Vec<Sense>| {
+            word(
+                WordTag::Both,
+                vec![senses, vec![sense(Noun, &["house"], &[])]],
+            )
+        };
+        for (policy, expected) in [
+            (
+                MergePolicy::PreferFirst,
+                mono(vec![sense(Noun, &["house"], &["kasa"])]),
+            ),
+            // Merged by POS, then sorted by it, with links sorted
+            (
+                MergePolicy::UnionSenses,
+                both(vec![
+                    sense(Noun, &["house", "home"], &["cassa", "kasa"]),
+                    sense(Verb, &["to marry"], &[]),
+                ]),
+            ),
+            // One after another, the repeated sense adding its links
+            (
+                MergePolicy::KeepAll,
+                both(vec![
+                    sense(Noun, &["house"], &["kasa", "cassa"]),
+                    sense(Verb, &["to marry"], &[]),
+                    sense(Noun, &["home", "house"], &[]),
+                ]),
+            ),
+        ] {
+            let mut merged = base.clone();
+            merge_word(&mut merged, other.clone(), policy);
+            assert_eq!(
+                format!("{:?}", merged),
+                format!("{:?}", expected),
+                "{:?}",
+                policy
+            );
+        }
+    }
+
+    #[test]
+    fn test_merge_dictionaries() {
+        let write = |words: &[(&str, &str, &str, bool)], forms: &[(&str, &str)], opts| {
+            let tagged_words = tagged_words(words);
+            write_dict(&tagged_words, &plural_forms(forms, &tagged_words), &opts)
+        };
+        let base = write(
+            &[
+                ("casa", "noun", "house", true),
+                ("perro", "noun", "dog", true),
+                ("gato", "noun", "cat", true),
+            ],
+            &[("perros", "perro")],
+            WriterOptions {
+                offsets: Offsets::Varint,
+                restart_interval: 2,
+                bloom_bits_per_key: 15,
+                string_table_min_count: 1,
+                frequency_list: vec!["casa".to_string()],
+                hot_words: 1,
+                ..Default::default()
+            },
+        );
+        let glossary = write(
+            &[
+                ("casa", "noun", "home", true),
+                ("casa", "noun", "house", true),
+                ("casa", "verb", "to marry", true),
+                ("sprint", "noun", "iteration", false),
+            ],
+            &[("sprints", "sprint")],
+            WriterOptions {
+                codec: Codec::Lz4,
+                layout: StreamLayout::Interleaved,
+                ..Default::default()
+            },
+        );
+
+        // The same casa sense as the base's
+        let repeated = write(&[("casa", "noun", "house", true)], &[], Default::default());
+
+        let mut base_reader = DictionaryReader::open(Cursor::new(base.clone())).unwrap();
+        // What dictmerge copies to the merged dictionary
+        assert!(matches!(base_reader.offsets(), Offsets::Varint));
+        assert!(matches!(base_reader.layout(), StreamLayout::Split));
+        assert_eq!(base_reader.restart_interval(), 2);
+        assert_eq!(base_reader.hot_words(), ["casa"]);
+        // 3 words and a form, rounded up to 64 bits
+        assert_eq!(base_reader.bloom_bits_per_key().unwrap(), 16);
+        let glossary_reader = DictionaryReader::open(Cursor::new(glossary.clone())).unwrap();
+        assert!(matches!(
+            glossary_reader.layout(),
+            StreamLayout::Interleaved
+        ));
+        let words = base_reader.words().unwrap();
+        assert_eq!(words.len(), 3);
+        for word in &words {
+            let found = base_reader.lookup_exact(&word.word).unwrap().unwrap();
+            assert_eq!(format!("{:?}", word), format!("{:?}", found));
+        }
+
+        for (policy, casa_glosses) in [
+            (MergePolicy::PreferFirst, vec![vec!["house"]]),
+            (
+                MergePolicy::UnionSenses,
+                vec![vec!["house", "home"], vec!["to marry"]],
+            ),
+            (
+                MergePolicy::KeepAll,
+                vec![vec!["house"], vec!["home", "house"], vec!["to marry"]],
+            ),
+        ] {
+            let mut readers = [&base, &glossary, &repeated]
+                .map(|b| DictionaryReader::open(Cursor::new(b.clone())).unwrap());
+            let mut merged = Vec::new();
+            let writer = DictionaryWriter::new().timestamp(0);
+            let count = merge_dictionaries(&mut readers, policy, &writer, &mut merged).unwrap();
+            assert_eq!(count.word_count, 4);
+
+            let mut dict_reader = DictionaryReader::open(Cursor::new(merged)).unwrap();
+            let casa = dict_reader.lookup("casa").unwrap().unwrap();
+            let glosses: Vec<Vec<&str>> = (casa.entries[0].senses.iter())
+                .map(|s| {
+                    (s.glosses.iter())
+                        .map(|g| g.gloss_lines[0].text().unwrap())
+                        .collect()
+                })
+                .collect();
+            assert_eq!(glosses, casa_glosses, "{:?}", policy);
+            // Every sense has its own id
+            let ids: HashSet<u64> = casa.senses_with_ids().map(|(id, _)| id).collect();
+            assert_eq!(ids.len(), casa_glosses.len(), "{:?}", policy);
+            for (id, sense) in casa.senses_with_ids() {
+                let found = dict_reader.lookup_sense("casa", id).unwrap().unwrap();
+                assert_eq!(format!("{:?}", found), format!("{:?}", sense));
+            }
+            let sprint = dict_reader.lookup("sprints").unwrap().unwrap();
+            assert_eq!(sprint.word, "sprint");
+            assert!(matches!(sprint.tag, WordTag::English));
+            assert_eq!(dict_reader.lookup("perros").unwrap().unwrap().word, "perro");
+        }
+    }
+}
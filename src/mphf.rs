@@ -0,0 +1,265 @@
+//! Minimal perfect hash from the dictionary's keys to their ordinals, for
+//! exact lookups that skip Level 1 and Level 2.
+//!
+//! Keys are hashed into buckets of ~4 keys. Each bucket gets a pilot, found
+//! at build time, that moves all its keys to free slots of a table with one
+//! slot per key. A key that isn't in the dictionary still maps to some slot,
+//! so hits are verified against the stored key.
+
+/// Average keys per bucket: more buckets = less pilots to search, but a
+/// bigger section
+const KEYS_PER_BUCKET: usize = 4;
+/// Pilots tried per bucket before starting over with another seed
+const MAX_PILOT: u32 = 1 << 24;
+
+/// FNV-1a, then the splitmix64 finalizer to spread the bits. Sense and
+/// gloss ids are built on it, so it must not change.
+pub(crate) fn hash64(key: &[u8], seed: u64) -> u64 {
+    let mut h = 0xcbf29ce484222325 ^ seed;
+    for b in key {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    mix(h)
+}
+
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+fn slot(h: u64, pilot: u32, n: usize) -> usize {
+    ((h ^ mix(pilot as u64)) % n as u64) as usize
+}
+
+pub struct Mphf {
+    seed: u64,
+    pilots: Vec<u32>,
+    n: usize,
+}
+
+impl Mphf {
+    /// `keys` must be unique
+    pub fn build(keys: &[&[u8]]) -> Mphf {
+        let n = keys.len();
+        let bucket_count = n.div_ceil(KEYS_PER_BUCKET).max(1);
+        for seed in 0.. {
+            if let Some(pilots) = Self::try_build(keys, seed, bucket_count) {
+                return Mphf { seed, pilots, n };
+            }
+        }
+        unreachable!()
+    }
+
+    fn try_build(keys: &[&[u8]], seed: u64, bucket_count: usize) -> Option<Vec<u32>> {
+        let n = keys.len();
+        let mut buckets: Vec<Vec<u64>> = vec![Vec::new(); bucket_count];
+        for key in keys {
+            let h = hash64(key, seed);
+            buckets[(h % bucket_count as u64) as usize].push(h);
+        }
+        // Largest buckets first, while most slots are free
+        let mut order: Vec<usize> = (0..bucket_count).collect();
+        order.sort_by_key(|&b| std::cmp::Reverse(buckets[b].len()));
+
+        let mut pilots = vec![0u32; bucket_count];
+        let mut taken = vec![false; n];
+        let mut slots = Vec::with_capacity(KEYS_PER_BUCKET * 4);
+        for b in order {
+            let hashes = &buckets[b];
+            if hashes.is_empty() {
+                break;
+            }
+            let found = (0..MAX_PILOT).find(|&pilot| {
+                slots.clear();
+                for h in hashes {
+                    let s = slot(*h, pilot, n);
+                    if taken[s] || slots.contains(&s) {
+                        return false;
+                    }
+                    slots.push(s);
+                }
+                true
+            })?;
+            pilots[b] = found;
+            for s in &slots {
+                taken[*s] = true;
+            }
+        }
+        Some(pilots)
+    }
+
+    /// Slot of `key`, in `0..n`. Arbitrary for keys that weren't built in.
+    pub fn index(&self, key: &[u8]) -> Option<usize> {
+        if self.n == 0 {
+            return None;
+        }
+        let h = hash64(key, self.seed);
+        let pilot = self.pilots[(h % self.pilots.len() as u64) as usize];
+        Some(slot(h, pilot, self.n))
+    }
+}
+
+/// Contents of the `MPHF` section: the hash, which ordinal is at each of its
+/// slots, and each ordinal's key and binary data range.
+pub struct MphfIndex {
+    mphf: Mphf,
+    /// Slot -> ordinal
+    ordinals: Vec<u32>,
+    /// Ordinal -> start of its key in `keys`, plus the end
+    key_offsets: Vec<u32>,
+    keys: Vec<u8>,
+    /// Ordinal -> start of its binary data, relative to the words' binary
+    /// data, plus the end
+    binary_offsets: Vec<u64>,
+}
+
+impl MphfIndex {
+    /// `entries` are the keys with their binary data start, by ordinal;
+    /// `binary_end` is where the last one's data ends
+    pub fn build(entries: &[(&str, u64)], binary_end: u64) -> Result<MphfIndex, String> {
+        let n = u32::try_from(entries.len())
+            .map_err(|_| format!("Too many keys for the MPHF: {}", entries.len()))?;
+        let key_bytes: Vec<&[u8]> = entries.iter().map(|(k, _)| k.as_bytes()).collect();
+        let mphf = Mphf::build(&key_bytes);
+
+        let mut ordinals = vec![0u32; n as usize];
+        let mut key_offsets = Vec::with_capacity(entries.len() + 1);
+        let mut keys = Vec::new();
+        for (ordinal, key) in key_bytes.iter().enumerate() {
+            ordinals[mphf.index(key).unwrap()] = ordinal as u32;
+            key_offsets.push(keys.len() as u32);
+            keys.extend_from_slice(key);
+            if keys.len() > u32::MAX as usize {
+                return Err("MPHF keys over 4 GiB".to_string());
+            }
+        }
+        key_offsets.push(keys.len() as u32);
+
+        let mut binary_offsets: Vec<u64> = entries.iter().map(|(_, off)| *off).collect();
+        binary_offsets.push(binary_end);
+        Ok(MphfIndex {
+            mphf,
+            ordinals,
+            key_offsets,
+            keys,
+            binary_offsets,
+        })
+    }
+
+    /// Ordinal, binary data offset (relative to the words' binary data) and
+    /// size of `key`
+    pub fn get(&self, key: &str) -> Option<(u32, u64, u64)> {
+        let ordinal = self.ordinals[self.mphf.index(key.as_bytes())?] as usize;
+        let key_range = self.key_offsets[ordinal] as usize..self.key_offsets[ordinal + 1] as usize;
+        if &self.keys[key_range] != key.as_bytes() {
+            return None;
+        }
+        let start = self.binary_offsets[ordinal];
+        let end = self.binary_offsets[ordinal + 1];
+        Some((ordinal as u32, start, end - start))
+    }
+
+    /// Key, binary data offset (relative to the words' binary data) and size
+    /// of `ordinal`
+    pub fn entry(&self, ordinal: u32) -> Option<(&[u8], u64, u64)> {
+        let ordinal = ordinal as usize;
+        if ordinal >= self.ordinals.len() {
+            return None;
+        }
+        let key_range = self.key_offsets[ordinal] as usize..self.key_offsets[ordinal + 1] as usize;
+        let start = self.binary_offsets[ordinal];
+        let end = self.binary_offsets[ordinal + 1];
+        Some((&self.keys[key_range], start, end - start))
+    }
+
+    /// seed (8 B) | n (4 B) | bucket count (4 B) | pilots (4 B each)
+    /// | ordinals (4 B each) | key offsets (4 B each, n + 1)
+    /// | binary offsets (8 B each, n + 1) | keys, all LE
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let n = self.ordinals.len();
+        let mut b = Vec::with_capacity(16 + self.mphf.pilots.len() * 4 + n * 16 + self.keys.len());
+        b.extend(self.mphf.seed.to_le_bytes());
+        b.extend((n as u32).to_le_bytes());
+        b.extend((self.mphf.pilots.len() as u32).to_le_bytes());
+        for p in &self.mphf.pilots {
+            b.extend(p.to_le_bytes());
+        }
+        for o in &self.ordinals {
+            b.extend(o.to_le_bytes());
+        }
+        for o in &self.key_offsets {
+            b.extend(o.to_le_bytes());
+        }
+        for o in &self.binary_offsets {
+            b.extend(o.to_le_bytes());
+        }
+        b.extend(&self.keys);
+        b
+    }
+
+    pub fn from_bytes(b: &[u8]) -> Result<Self, String> {
+        let truncated = || "Truncated MPHF section".to_string();
+        if b.len() < 16 {
+            return Err(truncated());
+        }
+        let seed = u64::from_le_bytes(b[..8].try_into().unwrap());
+        let n = u32::from_le_bytes(b[8..12].try_into().unwrap()) as usize;
+        let bucket_count = u32::from_le_bytes(b[12..16].try_into().unwrap()) as usize;
+        let fixed_len = (bucket_count + 2 * n + 1) * 4 + (n + 1) * 8;
+        let mut rest = b[16..].get(..fixed_len).ok_or_else(truncated)?;
+        let mut u32s = |count: usize| -> Vec<u32> {
+            let (head, tail) = rest.split_at(count * 4);
+            rest = tail;
+            head.chunks_exact(4)
+                .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+                .collect()
+        };
+        let pilots = u32s(bucket_count);
+        let ordinals = u32s(n);
+        let key_offsets = u32s(n + 1);
+        let binary_offsets: Vec<u64> = rest
+            .chunks_exact(8)
+            .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        let keys = b[16 + fixed_len..].to_vec();
+
+        let valid = bucket_count > 0
+            && ordinals.iter().all(|&o| (o as usize) < n)
+            && key_offsets.is_sorted()
+            && key_offsets
+                .last()
+                .is_some_and(|&end| end as usize <= keys.len())
+            && binary_offsets.is_sorted();
+        if !valid {
+            return Err("Malformed MPHF section".to_string());
+        }
+        Ok(MphfIndex {
+            mphf: Mphf { seed, pilots, n },
+            ordinals,
+            key_offsets,
+            keys,
+            binary_offsets,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mphf_is_minimal_and_perfect() {
+        let keys: Vec<String> = (0..5000).map(|i| format!("word{}", i)).collect();
+        let key_bytes: Vec<&[u8]> = keys.iter().map(|k| k.as_bytes()).collect();
+        let mphf = Mphf::build(&key_bytes);
+        let mut seen = vec![false; keys.len()];
+        for k in &key_bytes {
+            let i = mphf.index(k).unwrap();
+            assert!(!seen[i], "slot {} used twice", i);
+            seen[i] = true;
+        }
+        assert!(Mphf::build(&[]).index(b"a").is_none());
+    }
+}
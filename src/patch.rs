@@ -0,0 +1,362 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+
+pub const PATCH_MAGIC: &[u8; 4] = b"DPAT";
+pub const PATCH_VERSION: u8 = 1;
+
+// Matches are searched for on blocks of this many bytes; shorter runs are
+// emitted as literals
+const BLOCK_SIZE: usize = 32;
+// Caps how many candidate offsets we remember for a single block hash, so
+// highly repetitive inputs don't turn the search quadratic
+const MAX_CANDIDATES: usize = 8;
+const HASH_BASE: u64 = 0x100000001b3;
+
+const OP_END: u8 = 0;
+const OP_COPY: u8 = 1;
+const OP_INSERT: u8 = 2;
+
+pub type Sha256Digest = [u8; 32];
+
+#[derive(Debug)]
+pub enum PatchError {
+    InvalidPatch(&'static str),
+    OldDigestMismatch,
+    NewDigestMismatch,
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::InvalidPatch(msg) => write!(f, "Invalid patch: {}", msg),
+            PatchError::OldDigestMismatch => {
+                write!(f, "Patch does not apply: source file digest mismatch")
+            }
+            PatchError::NewDigestMismatch => {
+                write!(f, "Patched file does not match the expected digest")
+            }
+        }
+    }
+}
+
+impl StdError for PatchError {}
+
+pub fn digest(data: &[u8]) -> Sha256Digest {
+    Sha256::digest(data).into()
+}
+
+/// Produces a patch that turns `old` into `new`.
+///
+/// The patch is a sequence of copy (from `old`) and insert (literal bytes)
+/// operations, prefixed by the length and SHA-256 digest of both files.
+pub fn diff(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(128);
+    out.extend_from_slice(PATCH_MAGIC);
+    out.push(PATCH_VERSION);
+    out.extend_from_slice(&(old.len() as u64).to_le_bytes());
+    out.extend_from_slice(&digest(old));
+    out.extend_from_slice(&(new.len() as u64).to_le_bytes());
+    out.extend_from_slice(&digest(new));
+
+    let mut blocks: HashMap<u64, Vec<usize>> = HashMap::new();
+    for off in (0..old.len().saturating_sub(BLOCK_SIZE - 1)).step_by(BLOCK_SIZE) {
+        let candidates = blocks
+            .entry(block_hash(&old[off..off + BLOCK_SIZE]))
+            .or_default();
+        if candidates.len() < MAX_CANDIDATES {
+            candidates.push(off);
+        }
+    }
+
+    // HASH_BASE^(BLOCK_SIZE-1), to remove the outgoing byte when rolling
+    let top_factor = (1..BLOCK_SIZE).fold(1u64, |acc, _| acc.wrapping_mul(HASH_BASE));
+
+    let mut literal_start = 0;
+    let mut pos = 0;
+    let mut hash = None;
+    while pos + BLOCK_SIZE <= new.len() {
+        let h = *hash.get_or_insert_with(|| block_hash(&new[pos..pos + BLOCK_SIZE]));
+
+        let best = blocks.get(&h).and_then(|candidates| {
+            candidates
+                .iter()
+                .filter(|&&off| old[off..off + BLOCK_SIZE] == new[pos..pos + BLOCK_SIZE])
+                .map(|&off| (off, common_len(&old[off..], &new[pos..])))
+                .max_by_key(|&(_, len)| len)
+        });
+
+        match best {
+            Some((mut old_off, mut len)) => {
+                let mut new_off = pos;
+                // Grow the match backwards into the pending literal run
+                while new_off > literal_start && old_off > 0 && old[old_off - 1] == new[new_off - 1]
+                {
+                    old_off -= 1;
+                    new_off -= 1;
+                    len += 1;
+                }
+                write_insert(&mut out, &new[literal_start..new_off]);
+                out.push(OP_COPY);
+                write_varint(&mut out, old_off as u64);
+                write_varint(&mut out, len as u64);
+
+                pos = new_off + len;
+                literal_start = pos;
+                hash = None;
+            }
+            None => {
+                if pos + BLOCK_SIZE < new.len() {
+                    let outgoing = (new[pos] as u64).wrapping_mul(top_factor);
+                    hash = Some(
+                        h.wrapping_sub(outgoing)
+                            .wrapping_mul(HASH_BASE)
+                            .wrapping_add(new[pos + BLOCK_SIZE] as u64),
+                    );
+                }
+                pos += 1;
+            }
+        }
+    }
+    write_insert(&mut out, &new[literal_start..]);
+    out.push(OP_END);
+    out
+}
+
+/// Applies `patch` to `old`, returning the reconstructed file.
+///
+/// Both the source and the result are checked against the digests stored in
+/// the patch.
+pub fn apply(old: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut p = PatchCursor {
+        data: patch,
+        pos: 0,
+    };
+    if p.take(4)? != PATCH_MAGIC {
+        return Err(PatchError::InvalidPatch("bad magic"));
+    }
+    if p.take(1)?[0] != PATCH_VERSION {
+        return Err(PatchError::InvalidPatch("unsupported version"));
+    }
+    let old_len = p.u64()?;
+    let old_digest = p.take(32)?;
+    let new_len = p.u64()?;
+    let new_digest = p.take(32)?;
+
+    if old_len != old.len() as u64 || old_digest != digest(old) {
+        return Err(PatchError::OldDigestMismatch);
+    }
+
+    // The header's length isn't trusted until the digest is checked: the
+    // file can't be longer than what the patch copies and inserts
+    let max_len = (old.len() as u64).saturating_add(patch.len() as u64);
+    let mut out = Vec::with_capacity(new_len.min(max_len) as usize);
+    loop {
+        match p.take(1)?[0] {
+            OP_END => break,
+            OP_COPY => {
+                let off = p.varint()? as usize;
+                let len = p.varint()? as usize;
+                let src = off
+                    .checked_add(len)
+                    .and_then(|end| old.get(off..end))
+                    .ok_or(PatchError::InvalidPatch("copy out of bounds"))?;
+                out.extend_from_slice(src);
+            }
+            OP_INSERT => {
+                let len = p.varint()? as usize;
+                out.extend_from_slice(p.take(len)?);
+            }
+            _ => return Err(PatchError::InvalidPatch("unknown operation")),
+        }
+        if out.len() as u64 > new_len {
+            return Err(PatchError::InvalidPatch("longer than the new file"));
+        }
+    }
+
+    if out.len() as u64 != new_len || digest(&out) != new_digest {
+        return Err(PatchError::NewDigestMismatch);
+    }
+    Ok(out)
+}
+
+fn block_hash(block: &[u8]) -> u64 {
+    block.iter().fold(0u64, |h, &b| {
+        h.wrapping_mul(HASH_BASE).wrapping_add(b as u64)
+    })
+}
+
+fn common_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+fn write_insert(out: &mut Vec<u8>, literal: &[u8]) {
+    if literal.is_empty() {
+        return;
+    }
+    out.push(OP_INSERT);
+    write_varint(out, literal.len() as u64);
+    out.extend_from_slice(literal);
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+struct PatchCursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PatchCursor<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], PatchError> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.data.len())
+            .ok_or(PatchError::InvalidPatch("unexpected end of patch"))?;
+        let s = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(s)
+    }
+
+    fn u64(&mut self) -> Result<u64, PatchError> {
+        let b = self.take(8)?;
+        Ok(u64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn varint(&mut self) -> Result<u64, PatchError> {
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.take(1)?[0];
+            v |= ((b & 0x7F) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err(PatchError::InvalidPatch("varint too long"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudo_random(len: usize, seed: u64) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_apply_roundtrip() {
+        let old = pseudo_random(64 * 1024, 1);
+        let mut new = old.clone();
+        new[100..140].copy_from_slice(&pseudo_random(40, 2));
+        new.splice(20_000..20_000, pseudo_random(3000, 3));
+        new.drain(50_000..51_000);
+        new.extend_from_slice(b"appended tail");
+
+        let patch = diff(&old, &new);
+        assert!(patch.len() < 4 * 1024, "patch too big: {}", patch.len());
+        assert_eq!(apply(&old, &patch).unwrap(), new);
+
+        // Degenerate inputs
+        assert_eq!(apply(&[], &diff(&[], &new)).unwrap(), new);
+        assert!(apply(&old, &diff(&old, &[])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_apply_rejects_wrong_source() {
+        let old = pseudo_random(4096, 4);
+        let new = pseudo_random(4096, 5);
+        let patch = diff(&old, &new);
+
+        let mut other = old.clone();
+        other[0] ^= 1;
+        assert!(matches!(
+            apply(&other, &patch),
+            Err(PatchError::OldDigestMismatch)
+        ));
+
+        let mut corrupted = patch.clone();
+        let last_literal = corrupted.len() - 2;
+        corrupted[last_literal] ^= 1;
+        assert!(matches!(
+            apply(&old, &corrupted),
+            Err(PatchError::NewDigestMismatch)
+        ));
+
+        // A huge new length, then a copy past the end of the old file
+        let mut hostile = patch[..5 + 8 + 32].to_vec();
+        hostile.extend(u64::MAX.to_le_bytes());
+        hostile.extend([0; 32]);
+        hostile.push(OP_COPY);
+        write_varint(&mut hostile, 4000);
+        write_varint(&mut hostile, 200);
+        assert!(matches!(
+            apply(&old, &hostile),
+            Err(PatchError::InvalidPatch("copy out of bounds"))
+        ));
+    }
+
+    #[test]
+    fn test_patch_between_builds() {
+        use crate::test_util::{mono_words, write_dict};
+        use crate::writer::WriterOptions;
+        use crate::{Compression, FrameSize, Grouping, RegionCompression, StreamLayout};
+
+        let words: Vec<String> = (0..20_000)
+            .map(|i| format!("sana{}x{}", i * 7919 % 10007, i))
+            .collect();
+        let old_words = mono_words(&words);
+        let mut new_words = words.clone();
+        new_words.push("sana5000y".to_string());
+        let new_words = mono_words(&new_words);
+
+        // Bounded groups and small frames, so that a change only rewrites a
+        // few of them
+        let region = RegionCompression {
+            level: 9,
+            frame_size: FrameSize::Uncompressed(64 * 1024),
+        };
+        for layout in [StreamLayout::Split, StreamLayout::Interleaved] {
+            let opts = WriterOptions {
+                layout,
+                grouping: Grouping::Bounded {
+                    max_group_size: 4096,
+                },
+                restart_interval: 16,
+                compression: Compression {
+                    level2: region,
+                    data: region,
+                },
+                ..Default::default()
+            };
+            let old = write_dict(&old_words, &[], &opts);
+            let new = write_dict(&new_words, &[], &opts);
+
+            // Only the groups and frames around the new word change
+            let patch = diff(&old, &new);
+            assert!(
+                patch.len() < new.len() / 5,
+                "{:?}: {} byte patch for a {} byte file",
+                layout,
+                patch.len(),
+                new.len()
+            );
+            assert_eq!(apply(&old, &patch).unwrap(), new);
+        }
+    }
+}
@@ -1,31 +1,241 @@
-use crate::{HEADER_SIZE, TARKKA_FMT_VERSION, WordWithTaggedEntries};
+use crate::bloom::BloomFilter;
+use crate::codec::StreamDecoder;
+use crate::de::{CompactDeserialize, VarU64};
+use crate::mphf::MphfIndex;
+use crate::section::{
+    SECTION_BLOOM, SECTION_COMPRESSION, SECTION_FORMS, SECTION_FST, SECTION_GROUPING,
+    SECTION_HOT_WORDS, SECTION_LAYOUT, SECTION_MPHF, SECTION_NORMALIZATION, SECTION_OFFSETS,
+    SECTION_ORDINALS, SECTION_RESTARTS, SECTION_SIGNATURE, SECTION_STRINGS, Section,
+    parse_sections,
+};
+use crate::signature::{self, SIGNATURE_LEN};
+use crate::strings::StringTable;
+use crate::{
+    Codec, Compression, FormEntry, FormOf, Grouping, HEADER_SIZE, Normalization, Offsets, Sense,
+    StreamLayout, TARKKA_FMT_VERSION, WordWithTaggedEntries, level1_key,
+};
+use fst::{Automaton, IntoStreamer, Streamer};
+use std::collections::HashMap;
 use std::io::Seek;
 use std::io::{Read, SeekFrom};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::{Duration, SystemTime};
 
 struct Level2GroupInfo {
-    group_offset: u32,
-    group_size: u32,
-    binary_base_offset: u32,
+    group_offset: u64,
+    group_size: u64,
+    binary_base_offset: u64,
 }
+
+struct Level1Entry {
+    /// Range of the key in `Index::level1_data`
+    key: std::ops::Range<usize>,
+    info: Level2GroupInfo,
+}
+
+/// How an index's Level 1 and Level 2 tables are encoded
+#[derive(Clone, Copy)]
+struct Layout {
+    grouping: Grouping,
+    offsets: Offsets,
+    /// Entries between restart points in Level 2 groups, 0 if none
+    restart_interval: u32,
+    stream: StreamLayout,
+}
+
+/// A Level 1 table, plus where its Level 2 groups and binary data live in
+/// the decompressed stream. Used both for the words and the forms index.
+struct Index {
+    layout: Layout,
+    level1_data: Vec<u8>,
+    entries: Vec<Level1Entry>,
+    level2_off: u64,
+    level2_size: u64,
+    binary_data_off: u64,
+    /// Ordinal of each group's first entry, empty if the file doesn't
+    /// record them
+    first_ordinals: Vec<u32>,
+}
+
+/// An entry found in a Level 2 group
+struct GroupEntry {
+    /// Relative to the group's binary data
+    binary_offset: u64,
+    binary_size: u64,
+    /// How many entries come before it, from where the scan started
+    position: u32,
+}
+
+/// Where an entry's binary data is in the decompressed stream
+struct Located {
+    offset: u64,
+    size: u64,
+    /// The entry's ordinal, if the index records them
+    ordinal: Option<u32>,
+}
+
+impl Index {
+    fn new(
+        layout: Layout,
+        level1_data: Vec<u8>,
+        level2_off: u64,
+        level2_size: u64,
+        binary_data_off: u64,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut entries = Vec::new();
+        let mut group_offset = 0u64;
+        let mut rest = level1_data.as_slice();
+
+        while !rest.is_empty() {
+            // Prefix3: 3 bytes key, Bounded: 1 byte key length + key
+            let key_len = match layout.grouping {
+                Grouping::Prefix3 => 3,
+                Grouping::Bounded { .. } => {
+                    let key_len = rest[0] as usize;
+                    rest = &rest[1..];
+                    key_len
+                }
+            };
+            if rest.len() < key_len {
+                return Err("Truncated level 1 entry".into());
+            }
+            let key_start = level1_data.len() - rest.len();
+            rest = &rest[key_len..];
+            // + size + binary offset
+            let group_size = layout.offsets.read(&mut rest)?;
+            let binary_base_offset = layout.offsets.read(&mut rest)?;
+
+            entries.push(Level1Entry {
+                key: key_start..key_start + key_len,
+                info: Level2GroupInfo {
+                    group_offset,
+                    group_size,
+                    binary_base_offset,
+                },
+            });
+            group_offset += group_size;
+        }
+
+        Ok(Index {
+            layout,
+            level1_data,
+            entries,
+            level2_off,
+            level2_size,
+            binary_data_off,
+            first_ordinals: Vec::new(),
+        })
+    }
+
+    /// The forms section payload is `l2 offset | l2 size | data offset`
+    /// followed by its Level 1 table.
+    fn from_forms_section(
+        layout: Layout,
+        payload: &[u8],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut rest = payload;
+        let level2_off = layout.offsets.read(&mut rest)?;
+        let level2_size = layout.offsets.read(&mut rest)?;
+        let binary_data_off = layout.offsets.read(&mut rest)?;
+        Index::new(
+            layout,
+            rest.to_vec(),
+            level2_off,
+            level2_size,
+            binary_data_off,
+        )
+    }
+
+    fn key(&self, entry: &Level1Entry) -> &[u8] {
+        &self.level1_data[entry.key.clone()]
+    }
+
+    /// Where the group's Level 2 entries start in the decompressed stream
+    ///
+    /// Offsets come from the file, so the sums saturate, and a corrupt one
+    /// fails as past the end of the stream in `StreamDecoder::read_at`
+    fn group_start(&self, info: &Level2GroupInfo) -> u64 {
+        let start = self.level2_off.saturating_add(info.group_offset);
+        match self.layout.stream {
+            StreamLayout::Split => start,
+            // The previous groups' binary data comes before it too
+            StreamLayout::Interleaved => start.saturating_add(info.binary_base_offset),
+        }
+    }
+
+    /// Where `offset`, relative to the group's binary data, is in the
+    /// decompressed stream
+    fn group_binary_offset(&self, info: &Level2GroupInfo, offset: u64) -> u64 {
+        match self.layout.stream {
+            StreamLayout::Split => (self.binary_data_off)
+                .saturating_add(info.binary_base_offset)
+                .saturating_add(offset),
+            StreamLayout::Interleaved => (self.group_start(info))
+                .saturating_add(info.group_size)
+                .saturating_add(offset),
+        }
+    }
+
+    /// Where `offset`, relative to the start of the index's binary data, is
+    /// in the decompressed stream
+    fn binary_offset(&self, offset: u64) -> u64 {
+        match self.layout.stream {
+            StreamLayout::Split => self.binary_data_off.saturating_add(offset),
+            StreamLayout::Interleaved => {
+                let group = (self.entries)
+                    .partition_point(|e| e.info.binary_base_offset <= offset)
+                    .saturating_sub(1);
+                let info = &self.entries[group].info;
+                self.group_binary_offset(info, offset.saturating_sub(info.binary_base_offset))
+            }
+        }
+    }
+
+    /// The Level 2 group that `word` would be in
+    fn find_level2_group(&self, word: &[u8]) -> Option<usize> {
+        match self.layout.grouping {
+            Grouping::Prefix3 if word.is_empty() => None,
+            Grouping::Prefix3 => {
+                let l1_group = level1_key(word);
+                self.entries
+                    .binary_search_by(|e| self.key(e).cmp(&l1_group))
+                    .ok()
+            }
+            Grouping::Bounded { .. } => {
+                // Last group whose separator sorts before the word
+                let i = self.entries.partition_point(|e| self.key(e) <= word);
+                i.checked_sub(1)
+            }
+        }
+    }
+}
+
+/// The file from `base_offset` on. The file is shared with the
+/// `DictionaryReader`, which reads all of it to verify its signature.
 struct OffsetFile<R: Read + Seek> {
-    reader: R,
+    reader: Arc<Mutex<R>>,
     base_offset: u64,
 }
 
 impl<R: Read + Seek> OffsetFile<R> {
-    fn new(mut r: R, base_offset: u64) -> std::io::Result<Self> {
-        r.seek(SeekFrom::Start(base_offset))?;
+    fn new(reader: Arc<Mutex<R>>, base_offset: u64) -> std::io::Result<Self> {
+        lock(&reader)?.seek(SeekFrom::Start(base_offset))?;
         Ok(Self {
-            reader: r,
+            reader,
             base_offset,
         })
     }
 }
 
+fn lock<R>(reader: &Mutex<R>) -> std::io::Result<MutexGuard<'_, R>> {
+    reader
+        .lock()
+        .map_err(|_| std::io::Error::other("Dictionary file lock poisoned"))
+}
+
 impl<R: Read + Seek> Read for OffsetFile<R> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.reader.read(buf)
+        lock(&self.reader)?.read(buf)
     }
 }
 
@@ -36,7 +246,7 @@ impl<R: Read + Seek> Seek for OffsetFile<R> {
             SeekFrom::Current(offset) => SeekFrom::Current(offset),
             SeekFrom::End(offset) => SeekFrom::End(offset),
         };
-        let result = self.reader.seek(adjusted_pos)?;
+        let result = lock(&self.reader)?.seek(adjusted_pos)?;
         Ok(result - self.base_offset)
     }
 }
@@ -44,11 +254,23 @@ impl<R: Read + Seek> Seek for OffsetFile<R> {
 pub struct DictionaryReader<'a, R: Read + Seek> {
     created_at: std::time::SystemTime,
     version: u8,
-    level1_data: Vec<u8>,
-    level2_size: u32,
-    binary_data_off: u32,
+    words: Index,
+    forms: Option<Index>,
+    mphf: Option<MphfIndex>,
+    fst: Option<fst::Map<Vec<u8>>>,
+    bloom: Option<BloomFilter>,
+    strings: Option<StringTable>,
+    /// Entries of the hot frame, resident for the reader's lifetime
+    hot: HashMap<String, Vec<u8>>,
+    build_id: Option<u64>,
+    normalization: Normalization,
+    codec: Codec,
+    compression: Option<Compression>,
     word_count: u32,
-    decoder: zeekstd::Decoder<'a, OffsetFile<R>>,
+    /// Absolute offset of the `SIGN` payload, and the signature
+    signature: Option<(u64, [u8; SIGNATURE_LEN])>,
+    file: Arc<Mutex<R>>,
+    decoder: StreamDecoder<'a, OffsetFile<R>>,
 }
 
 impl<'a, R: Read + Seek> DictionaryReader<'a, R> {
@@ -86,24 +308,153 @@ impl<'a, R: Read + Seek> DictionaryReader<'a, R> {
             .into());
         }
 
-        let mut _rsv_buf = [0u8; 7];
+        let mut codec_buf = [0u8; 1];
+        r.read_exact(&mut codec_buf)?;
+        let codec = Codec::try_from(codec_buf[0])?;
+
+        let mut _rsv_buf = [0u8; 2];
         r.read_exact(&mut _rsv_buf)?;
 
+        r.read_exact(&mut size_buf)?;
+        let sections_size = u32::from_le_bytes(size_buf);
+
         let mut level1_data = vec![0u8; level1_size as usize];
         r.read_exact(&mut level1_data)?;
 
-        let level2_off = level1_size + HEADER_SIZE as u32;
-        let offset_file = OffsetFile::new(r, level2_off as u64)?;
-        let decoder = zeekstd::Decoder::new(offset_file)?;
+        let mut sections_data = vec![0u8; sections_size as usize];
+        r.read_exact(&mut sections_data)?;
+        let sections = parse_sections(&sections_data)?;
+        let section = |tag: [u8; 4]| -> Option<&Section> { sections.iter().find(|s| s.tag == tag) };
+
+        // Files without a grouping section use 3 byte keys
+        let grouping = match section(SECTION_GROUPING) {
+            Some(s) => Grouping::from_bytes(&s.payload)?,
+            None => Grouping::Prefix3,
+        };
+
+        // The header's Level 2 size is only valid with 32-bit offsets
+        let (offsets, level2_size) = match section(SECTION_OFFSETS) {
+            Some(s) if s.payload.len() >= 9 => (
+                Offsets::try_from(s.payload[0])?,
+                u64::from_le_bytes(s.payload[1..9].try_into().unwrap()),
+            ),
+            Some(_) => return Err("Truncated offsets section".into()),
+            None => (Offsets::U32, level2_size as u64),
+        };
+
+        // Files without a restarts section have none
+        let restart_interval = match section(SECTION_RESTARTS) {
+            Some(s) if s.payload.len() >= 4 => {
+                u32::from_le_bytes(s.payload[..4].try_into().unwrap())
+            }
+            Some(_) => return Err("Truncated restarts section".into()),
+            None => 0,
+        };
+        // Files without a layout section are split
+        let stream = match section(SECTION_LAYOUT) {
+            Some(s) if !s.payload.is_empty() => StreamLayout::try_from(s.payload[0])?,
+            Some(_) => return Err("Truncated layout section".into()),
+            None => StreamLayout::Split,
+        };
+        let layout = Layout {
+            grouping,
+            offsets,
+            restart_interval,
+            stream,
+        };
+
+        let forms = match section(SECTION_FORMS) {
+            Some(s) => Some(Index::from_forms_section(layout, &s.payload)?),
+            None => None,
+        };
+
+        let mphf = match section(SECTION_MPHF) {
+            Some(s) => Some(MphfIndex::from_bytes(&s.payload)?),
+            None => None,
+        };
+
+        let fst = match section(SECTION_FST) {
+            Some(s) => Some(fst::Map::new(s.payload.clone())?),
+            None => None,
+        };
+
+        let bloom = match section(SECTION_BLOOM) {
+            Some(s) => Some(BloomFilter::from_bytes(&s.payload)?),
+            None => None,
+        };
+
+        // Build id (8 B) | first ordinal of each group (4 B each)
+        let mut words = Index::new(layout, level1_data, 0, level2_size, level2_size)?;
+        let build_id = match section(SECTION_ORDINALS) {
+            Some(s) if s.payload.len() == 8 + words.entries.len() * 4 => {
+                words.first_ordinals = (s.payload[8..].chunks_exact(4))
+                    .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+                    .collect();
+                Some(u64::from_le_bytes(s.payload[..8].try_into().unwrap()))
+            }
+            Some(_) => return Err("Malformed ordinals section".into()),
+            None => None,
+        };
+
+        let strings = match section(SECTION_STRINGS) {
+            Some(s) => Some(StringTable::from_bytes(&s.payload)?),
+            None => None,
+        };
+
+        // Decompressed size (8 B) | hot frame
+        let hot = match section(SECTION_HOT_WORDS) {
+            Some(s) if s.payload.len() >= 8 => {
+                let size = u64::from_le_bytes(s.payload[..8].try_into().unwrap());
+                let frame = std::io::Cursor::new(&s.payload[8..]);
+                let data = StreamDecoder::new(codec, frame)?.read_at(0, size)?;
+                parse_hot_frame(&data)?
+            }
+            Some(_) => return Err("Truncated hot words section".into()),
+            None => HashMap::new(),
+        };
+
+        let normalization = match section(SECTION_NORMALIZATION) {
+            Some(s) if !s.payload.is_empty() => Normalization::try_from(s.payload[0])?,
+            Some(_) => return Err("Truncated normalization section".into()),
+            None => Normalization::None,
+        };
+
+        let compression = match section(SECTION_COMPRESSION) {
+            Some(s) => Some(Compression::from_bytes(&s.payload)?),
+            None => None,
+        };
+
+        let sections_off = HEADER_SIZE as u64 + level1_size as u64;
+        let signature = match section(SECTION_SIGNATURE) {
+            Some(s) => match s.payload.as_slice().try_into() {
+                Ok(sig) => Some((sections_off + s.offset as u64, sig)),
+                Err(_) => return Err("Malformed signature section".into()),
+            },
+            None => None,
+        };
+
+        let level2_off = sections_off + sections_size as u64;
+        let file = Arc::new(Mutex::new(r));
+        let offset_file = OffsetFile::new(Arc::clone(&file), level2_off)?;
+        let decoder = StreamDecoder::new(codec, offset_file)?;
 
-        let binary_data_off = level2_size;
         Ok(DictionaryReader {
             created_at: SystemTime::UNIX_EPOCH + Duration::from_secs(timestamp_s),
             version: ver,
-            level1_data,
-            level2_size,
+            words,
+            forms,
+            mphf,
+            fst,
+            bloom,
+            strings,
+            hot,
+            build_id,
+            normalization,
+            codec,
+            compression,
             word_count,
-            binary_data_off,
+            signature,
+            file,
             decoder,
         })
     }
@@ -117,174 +468,1082 @@ impl<'a, R: Read + Seek> DictionaryReader<'a, R> {
     pub fn created_at(&self) -> SystemTime {
         self.created_at
     }
+    pub fn has_forms_index(&self) -> bool {
+        self.forms.is_some()
+    }
+    pub fn has_mphf(&self) -> bool {
+        self.mphf.is_some()
+    }
+    pub fn has_fst(&self) -> bool {
+        self.fst.is_some()
+    }
+    pub fn has_bloom_filter(&self) -> bool {
+        self.bloom.is_some()
+    }
+    pub fn has_string_table(&self) -> bool {
+        self.strings.is_some()
+    }
+    /// Number of words whose entries are kept decompressed in memory
+    pub fn hot_word_count(&self) -> usize {
+        self.hot.len()
+    }
+    /// The words whose entries are kept decompressed in memory, in key
+    /// order
+    pub fn hot_words(&self) -> Vec<&str> {
+        let mut words: Vec<&str> = self.hot.keys().map(|k| k.as_str()).collect();
+        words.sort_unstable();
+        words
+    }
+    /// Bits per key of the bloom filter, 0 without one. The keys include
+    /// the inflected forms, so this decodes the forms index to count them.
+    pub fn bloom_bits_per_key(&mut self) -> Result<u32, Box<dyn std::error::Error>> {
+        let Some(num_bits) = self.bloom.as_ref().map(BloomFilter::num_bits) else {
+            return Ok(0);
+        };
+        let keys = self.word_count as u64 + self.forms()?.len() as u64;
+        // The bit array is rounded up to whole 64-bit words
+        Ok((num_bits / keys.max(1)).max(1) as u32)
+    }
+    /// Identifies the order of the words, and so their ids: ids from a
+    /// dictionary with another build id may point to other words. `None` if
+    /// the file doesn't record ordinals.
+    pub fn build_id(&self) -> Option<u64> {
+        self.build_id
+    }
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+    pub fn grouping(&self) -> Grouping {
+        self.words.layout.grouping
+    }
+    pub fn offsets(&self) -> Offsets {
+        self.words.layout.offsets
+    }
+    pub fn layout(&self) -> StreamLayout {
+        self.words.layout.stream
+    }
+    /// Every how many Level 2 entries there's a restart point, 0 for none
+    pub fn restart_interval(&self) -> u32 {
+        self.words.layout.restart_interval
+    }
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+    pub fn is_signed(&self) -> bool {
+        self.signature.is_some()
+    }
+
+    /// Checks the embedded signature against `public_key` (ed25519, 32 B).
+    /// Reads the whole file. Fails if the dictionary isn't signed.
+    pub fn verify_signature(
+        &mut self,
+        public_key: &[u8; 32],
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let (offset, sig) = self.signature.ok_or("Dictionary is not signed")?;
+        let mut file = lock(&self.file)?;
+        // The decoder may expect the file where it left it
+        let pos = file.stream_position()?;
+        file.seek(SeekFrom::Start(0))?;
+        let digest = signature::file_digest(&mut *file, offset);
+        file.seek(SeekFrom::Start(pos))?;
+        Ok(signature::verify(public_key, &digest?, &sig)?)
+    }
+
+    /// The settings the stream was compressed with, if recorded
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    fn key_set(&self) -> Result<&fst::Map<Vec<u8>>, Box<dyn std::error::Error>> {
+        self.fst
+            .as_ref()
+            .ok_or_else(|| "Dictionary has no FST section".into())
+    }
+
+    /// Words, with their ordinals, accepted by `automaton`, in byte order
+    pub fn search<A: fst::Automaton>(
+        &self,
+        automaton: A,
+    ) -> Result<Vec<(String, u64)>, Box<dyn std::error::Error>> {
+        Ok(self
+            .key_set()?
+            .search(automaton)
+            .into_stream()
+            .into_str_vec()?)
+    }
+
+    /// Words that match `pattern` as a whole, eg: `c.s+a`
+    pub fn search_regex(
+        &self,
+        pattern: &str,
+    ) -> Result<Vec<(String, u64)>, Box<dyn std::error::Error>> {
+        let dfa = regex_automata::dense::Builder::new()
+            .anchored(true)
+            .build(pattern)?;
+        self.search(&dfa)
+    }
+
+    /// Words at most `distance` edits away from `word`
+    pub fn search_fuzzy(
+        &self,
+        word: &str,
+        distance: u32,
+    ) -> Result<Vec<(String, u64)>, Box<dyn std::error::Error>> {
+        let normalized = self.normalization.apply(word);
+        self.search(fst::automaton::Levenshtein::new(&normalized, distance)?)
+    }
 
+    /// Words from `start` (inclusive) to `end` (exclusive), in byte order
+    pub fn search_range(
+        &self,
+        start: &str,
+        end: &str,
+    ) -> Result<Vec<(String, u64)>, Box<dyn std::error::Error>> {
+        let (start, end) = (
+            self.normalization.apply(start),
+            self.normalization.apply(end),
+        );
+        let range = self.key_set()?.range().ge(start.as_ref()).lt(end.as_ref());
+        Ok(range.into_stream().into_str_vec()?)
+    }
+
+    /// Number of words starting with `prefix`
+    pub fn count_prefix(&self, prefix: &str) -> Result<usize, Box<dyn std::error::Error>> {
+        let normalized = self.normalization.apply(prefix);
+        let starts_with = fst::automaton::Str::new(&normalized).starts_with();
+        let mut stream = self.key_set()?.search(starts_with).into_stream();
+        let mut count = 0;
+        while stream.next().is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Looks up `word`; if it is not a headword but an inflected form of one,
+    /// returns the (first) lemma's entry instead. The returned entry's `word`
+    /// tells which one was found.
     pub fn lookup(
         &mut self,
         word: &str,
     ) -> Result<Option<WordWithTaggedEntries>, Box<dyn std::error::Error>> {
-        let word_bytes = word.as_bytes();
-        let l1_group = match word_bytes.len() {
-            0 => return Err("Empty word".into()),
-            1 => [0, 0, word_bytes[0]],
-            2 => [0, word_bytes[0], word_bytes[1]],
-            _ => [word_bytes[0], word_bytes[1], word_bytes[2]],
+        if let Some(w) = self.lookup_exact(word)? {
+            return Ok(Some(w));
+        }
+        for form_of in self.lookup_forms(word)? {
+            if let Some(w) = self.lookup_exact(&form_of.lemma)? {
+                return Ok(Some(w));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Looks up `word` as a headword, without falling back to the forms index.
+    pub fn lookup_exact(
+        &mut self,
+        word: &str,
+    ) -> Result<Option<WordWithTaggedEntries>, Box<dyn std::error::Error>> {
+        let normalized = self.normalization.apply(word);
+        let word = normalized.as_ref();
+        match self.locate_word(word)? {
+            Some(located) => Ok(Some(self.read_word(word.to_string(), located)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Looks up one sense of the headword `word`, by a sense or gloss id,
+    /// see [`WordWithTaggedEntries::sense_by_id`]
+    pub fn lookup_sense(
+        &mut self,
+        word: &str,
+        id: u64,
+    ) -> Result<Option<Sense>, Box<dyn std::error::Error>> {
+        Ok(self.lookup_exact(word)?.and_then(|w| w.sense_by_id(id)))
+    }
+
+    /// Looks up the word whose [`id`](WordWithTaggedEntries::id) is `id`.
+    /// Ids are ordinals in the dictionary's key order, and only stay valid
+    /// for the same [`build_id`](Self::build_id).
+    pub fn lookup_by_id(
+        &mut self,
+        id: u32,
+    ) -> Result<Option<WordWithTaggedEntries>, Box<dyn std::error::Error>> {
+        if id >= self.word_count {
+            return Ok(None);
+        }
+        let (word, located) = match &self.mphf {
+            Some(mphf) => {
+                let (key, offset, size) = mphf.entry(id).ok_or("Ordinal missing from MPHF")?;
+                let located = Located {
+                    offset: self.words.binary_offset(offset),
+                    size,
+                    ordinal: Some(id),
+                };
+                (String::from_utf8(key.to_vec())?, located)
+            }
+            None => match Self::locate_ordinal(&mut self.decoder, &self.words, id)? {
+                Some(l) => l,
+                None => return Ok(None),
+            },
         };
+        Ok(Some(self.read_word(word, located)?))
+    }
 
-        let l2info = match self.find_level2_group_info(&l1_group)? {
-            Some(l) => l,
-            None => return Ok(None),
+    fn read_word(
+        &mut self,
+        word: String,
+        located: Located,
+    ) -> Result<WordWithTaggedEntries, Box<dyn std::error::Error>> {
+        // A 0 size marks an entry of the hot frame
+        let data = match located.size {
+            0 => match self.hot.get(&word) {
+                Some(data) => data.clone(),
+                None => return Err(format!("{} is missing from the hot frame", word).into()),
+            },
+            size => self.read_binary_data(located.offset, size)?,
         };
+        let mut parsed = WordWithTaggedEntries::named_deserialize(&mut data.as_slice(), word)
+            .map_err(|e| -> Box<dyn std::error::Error> { Box::from(e) })?;
+        if let Some(strings) = &self.strings {
+            strings.resolve(&mut parsed)?;
+        }
+        parsed.id = located.ordinal;
+        Ok(parsed)
+    }
 
-        let result = self.find_in_level2_group(l2info.group_offset, l2info.group_size, word)?;
+    /// Entries of the words that `word`'s senses link to (see
+    /// [`SenseLink`](crate::SenseLink)), so a UI can render "plural of
+    /// [perro]" and inline the lemma's definition. Targets missing from the
+    /// dictionary are left out.
+    pub fn resolve_links(
+        &mut self,
+        word: &WordWithTaggedEntries,
+    ) -> Result<Vec<WordWithTaggedEntries>, Box<dyn std::error::Error>> {
+        let mut resolved = Vec::new();
+        for target in word.link_targets() {
+            if let Some(w) = self.lookup_exact(target)? {
+                resolved.push(w);
+            }
+        }
+        Ok(resolved)
+    }
 
-        let (relative_binary_offset, binary_size) = match result {
-            None => return Ok(None),
-            Some(r) => (r.0, r.1),
+    /// Lemmas that `form` is an inflection of, eg: `perros` -> `perro`.
+    /// Empty if the form is unknown or the dictionary has no forms index.
+    pub fn lookup_forms(&mut self, form: &str) -> Result<Vec<FormOf>, Box<dyn std::error::Error>> {
+        let normalized = self.normalization.apply(form);
+        let form = normalized.as_ref();
+        let located = match self.locate_form(form)? {
+            Some(l) => l,
+            None => return Ok(vec![]),
         };
+        let data = self.read_binary_data(located.offset, located.size)?;
+        Ok(FormEntry::deserialize(&mut data.as_slice())?.lemmas)
+    }
+
+    /// Every headword's entry, in key order. Decompresses each group and
+    /// its data once, so it's much faster than looking the words up.
+    pub fn words(&mut self) -> Result<Vec<WordWithTaggedEntries>, Box<dyn std::error::Error>> {
+        let mut words = Vec::with_capacity(self.word_count as usize);
+        let strings = self.strings.as_ref();
+        Self::scan(
+            &mut self.decoder,
+            &self.words,
+            &self.hot,
+            |word, mut data, ordinal| {
+                let mut parsed = WordWithTaggedEntries::deserialize(&mut data)?;
+                if let Some(strings) = strings {
+                    strings.resolve(&mut parsed)?;
+                }
+                parsed.word = word;
+                parsed.id = ordinal;
+                words.push(parsed);
+                Ok(())
+            },
+        )?;
+        Ok(words)
+    }
 
-        let absolute_binary_offset =
-            l2info.binary_base_offset + relative_binary_offset + self.binary_data_off;
-        match self.get_word_from_binary_data(absolute_binary_offset, binary_size, word) {
-            Ok(w) => Ok(Some(w)),
-            Err(e) => Err(e),
+    /// Every inflected form with its lemmas, in key order, as
+    /// [`DictionaryWriter::write`](crate::writer::DictionaryWriter::write)
+    /// takes them. Empty if the dictionary has no forms index.
+    pub fn forms(&mut self) -> Result<Vec<(String, FormEntry)>, Box<dyn std::error::Error>> {
+        let mut forms = Vec::new();
+        if let Some(index) = &self.forms {
+            Self::scan(
+                &mut self.decoder,
+                index,
+                &HashMap::new(),
+                |form, mut data, _| {
+                    forms.push((form, FormEntry::deserialize(&mut data)?));
+                    Ok(())
+                },
+            )?;
         }
+        Ok(forms)
     }
 
-    fn find_level2_group_info(
-        &self,
-        l1_group: &[u8; 3],
-    ) -> Result<Option<Level2GroupInfo>, Box<dyn std::error::Error>> {
-        let mut pos = 0;
-        let mut group_offset = 0u32;
-
-        while pos + 11 <= self.level1_data.len() {
-            let key = [
-                self.level1_data[pos],
-                self.level1_data[pos + 1],
-                self.level1_data[pos + 2],
-            ];
-
-            let size_bytes = &self.level1_data[pos + 3..pos + 7];
-            let size =
-                u32::from_le_bytes([size_bytes[0], size_bytes[1], size_bytes[2], size_bytes[3]]);
-
-            if &key == l1_group {
-                let binary_offset_bytes = &self.level1_data[pos + 7..pos + 11];
-                let binary_offset = u32::from_le_bytes([
-                    binary_offset_bytes[0],
-                    binary_offset_bytes[1],
-                    binary_offset_bytes[2],
-                    binary_offset_bytes[3],
-                ]);
-                return Ok(Some(Level2GroupInfo {
-                    group_offset,
-                    group_size: size,
-                    binary_base_offset: binary_offset,
-                }));
+    /// Calls `f` with the key, binary data and ordinal of every entry of
+    /// `index`, in order. Entries of size 0 are taken from `hot`.
+    fn scan(
+        decoder: &mut StreamDecoder<'a, OffsetFile<R>>,
+        index: &Index,
+        hot: &HashMap<String, Vec<u8>>,
+        mut f: impl FnMut(String, &[u8], Option<u32>) -> Result<(), Box<dyn std::error::Error>>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let has_restarts = index.layout.restart_interval > 0;
+        for (group, entry) in index.entries.iter().enumerate() {
+            let info = &entry.info;
+            let group_start = index.group_start(info);
+            let decompressed = decoder.read_at(group_start, info.group_size)?;
+            let l2 = Level2Group::parse(&decompressed, has_restarts)?;
+            let mut cursor = Level2Cursor::new(l2.entries, 0, 0);
+            let mut located = Vec::new();
+            while let Some((offset, size)) = cursor.next_entry()? {
+                located.push((String::from_utf8(cursor.word.clone())?, offset, size));
             }
 
-            group_offset += size;
-            pos += 11; // 3 bytes key + 4 bytes size + 4 bytes binary offset
+            // The group's data is contiguous, and hot entries have none
+            let data_end =
+                (located.last()).map_or(0, |(_, offset, size)| offset.saturating_add(*size));
+            let data_start = index.group_binary_offset(info, 0);
+            let data = match data_end {
+                0 => Vec::new(),
+                _ => decoder.read_at(data_start, data_end)?,
+            };
+            for (position, (key, offset, size)) in located.into_iter().enumerate() {
+                let ordinal =
+                    (index.first_ordinals.get(group)).map(|first| first + position as u32);
+                let bytes = match size {
+                    0 => match hot.get(&key) {
+                        Some(bytes) => bytes.as_slice(),
+                        None => return Err(format!("{} is missing from the hot frame", key).into()),
+                    },
+                    _ => (data.get(offset as usize..offset.saturating_add(size) as usize))
+                        .ok_or("Entry past its group's binary data")?,
+                };
+                f(key, bytes, ordinal)?;
+            }
         }
+        Ok(())
+    }
 
-        Ok(None)
+    /// Whether `word` is a headword or an inflected form, without reading
+    /// its entry. With a bloom filter, most misses don't decompress anything.
+    pub fn contains(&mut self, word: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let normalized = self.normalization.apply(word);
+        let word = normalized.as_ref();
+        Ok(self.locate_word(word)?.is_some() || self.locate_form(word)?.is_some())
     }
 
-    fn find_in_level2_group(
-        &mut self,
-        group_offset: u32,
-        group_size: u32,
+    /// The bloom filter holds both words and forms, so a miss there is final
+    fn may_contain(&self, key: &str) -> bool {
+        self.bloom.as_ref().is_none_or(|b| b.may_contain(key))
+    }
+
+    /// Where the (normalized) headword's entry is
+    fn locate_word(&mut self, word: &str) -> Result<Option<Located>, Box<dyn std::error::Error>> {
+        if !word.is_empty() && !self.may_contain(word) {
+            return Ok(None);
+        }
+        // The MPHF holds every word, so a miss there is final
+        match &self.mphf {
+            Some(mphf) => Ok(mphf.get(word).map(|(ordinal, offset, size)| Located {
+                offset: self.words.binary_offset(offset),
+                size,
+                ordinal: Some(ordinal),
+            })),
+            None => Self::locate(&mut self.decoder, &self.words, word),
+        }
+    }
+
+    /// Where the (normalized) form's lemmas are
+    fn locate_form(&mut self, form: &str) -> Result<Option<Located>, Box<dyn std::error::Error>> {
+        let forms = match &self.forms {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+        if !form.is_empty() && !self.may_contain(form) {
+            return Ok(None);
+        }
+        Self::locate(&mut self.decoder, forms, form)
+    }
+
+    /// Finds `word` in `index`, returning its absolute offset and size in the
+    /// decompressed stream.
+    fn locate(
+        decoder: &mut StreamDecoder<'a, OffsetFile<R>>,
+        index: &Index,
         word: &str,
-    ) -> Result<Option<(u32, u16)>, Box<dyn std::error::Error>> {
-        let group_start = group_offset;
-        let group_end = group_start + group_size;
+    ) -> Result<Option<Located>, Box<dyn std::error::Error>> {
+        if word.is_empty() {
+            return Err("Empty word".into());
+        }
+        let group = match index.find_level2_group(word.as_bytes()) {
+            Some(g) => g,
+            None => return Ok(None),
+        };
+        let l2info = &index.entries[group].info;
 
-        if group_start >= self.level2_size || group_end > self.level2_size {
+        if l2info.group_offset >= index.level2_size
+            || l2info.group_offset + l2info.group_size > index.level2_size
+        {
             return Ok(None);
         }
 
-        self.decoder.set_offset(group_start as u64)?;
-        self.decoder.set_offset_limit(group_end as u64)?;
-        let mut decompressed = Vec::with_capacity(32768);
-        std::io::copy(&mut self.decoder, &mut decompressed)?;
+        let group_start = index.group_start(l2info);
+        let result = Self::find_in_level2_group(
+            decoder,
+            group_start,
+            l2info.group_size,
+            index.layout.restart_interval,
+            word,
+        )?;
+
+        let entry = match result {
+            None => return Ok(None),
+            Some(e) => e,
+        };
+
+        Ok(Some(Located {
+            offset: index.group_binary_offset(l2info, entry.binary_offset),
+            size: entry.binary_size,
+            ordinal: (index.first_ordinals.get(group)).map(|first| first + entry.position),
+        }))
+    }
+
+    /// Finds the word with ordinal `ordinal` in `index`, returning it and
+    /// its absolute offset and size in the decompressed stream.
+    fn locate_ordinal(
+        decoder: &mut StreamDecoder<'a, OffsetFile<R>>,
+        index: &Index,
+        ordinal: u32,
+    ) -> Result<Option<(String, Located)>, Box<dyn std::error::Error>> {
+        if index.first_ordinals.is_empty() {
+            return Err("Dictionary has no ordinals section".into());
+        }
+        let group = match (index.first_ordinals.partition_point(|&o| o <= ordinal)).checked_sub(1) {
+            Some(g) => g,
+            None => return Ok(None),
+        };
+        let l2info = &index.entries[group].info;
+        let group_start = index.group_start(l2info);
+        let decompressed = decoder.read_at(group_start, l2info.group_size)?;
+        let interval = index.layout.restart_interval;
+        let l2 = Level2Group::parse(&decompressed, interval > 0)?;
 
+        // Decode from the closest restart point before the entry, if any
+        let mut position = ordinal - index.first_ordinals[group];
+        let mut cursor = match interval {
+            0 => Level2Cursor::new(l2.entries, 0, 0),
+            _ => {
+                let restart = (position / interval) as usize;
+                if restart >= l2.restart_count() {
+                    return Ok(None);
+                }
+                position %= interval;
+                let (pos, binary_offset) = l2.restart(restart);
+                Level2Cursor::new(l2.entries, pos, binary_offset)
+            }
+        };
+        for _ in 0..position {
+            if cursor.next_entry()?.is_none() {
+                return Ok(None);
+            }
+        }
+        match cursor.next_entry()? {
+            Some((offset, size)) => Ok(Some((
+                String::from_utf8(cursor.word)?,
+                Located {
+                    offset: index.group_binary_offset(l2info, offset),
+                    size,
+                    ordinal: Some(ordinal),
+                },
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// Finds `word` in the group, with its position in the group
+    fn find_in_level2_group(
+        decoder: &mut StreamDecoder<'a, OffsetFile<R>>,
+        group_start: u64,
+        group_size: u64,
+        restart_interval: u32,
+        word: &str,
+    ) -> Result<Option<GroupEntry>, Box<dyn std::error::Error>> {
+        let decompressed = decoder.read_at(group_start, group_size)?;
         let wanted_word_b = word.as_bytes();
-        let mut pos = 0;
-        let mut current_word: Vec<u8> = Vec::with_capacity(128);
-        let mut binary_offset = 0u32;
+        if restart_interval == 0 {
+            return scan_level2_entries(&decompressed, 0, 0, wanted_word_b);
+        }
+
+        let l2 = Level2Group::parse(&decompressed, true)?;
+        // Restart entries store the full key: shared len 0 | len | key
+        let restart_key = |pos: usize| match l2.entries.get(pos..pos + 2) {
+            Some([0, len]) => l2.entries.get(pos + 2..pos + 2 + *len as usize),
+            _ => None,
+        };
+
+        // Last restart whose key is <= the word
+        let (mut lo, mut hi) = (0, l2.restart_count());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if restart_key(l2.restart(mid).0).ok_or(MALFORMED_RESTARTS)? <= wanted_word_b {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        if lo == 0 {
+            return Ok(None);
+        }
+        let (pos, binary_offset) = l2.restart(lo - 1);
+        let found = scan_level2_entries(l2.entries, pos, binary_offset, wanted_word_b)?;
+        let skipped = (lo as u32 - 1) * restart_interval;
+        Ok(found.map(|e| GroupEntry {
+            position: skipped + e.position,
+            ..e
+        }))
+    }
+
+    fn read_binary_data(
+        &mut self,
+        offset: u64,
+        size: u64,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        self.decoder.read_at(offset, size)
+    }
+}
+
+const MALFORMED_RESTARTS: &str = "malformed level 2 group, bad restarts";
+
+/// A decompressed Level 2 group, split into its entries and, with restart
+/// points, its restarts trailer
+struct Level2Group<'d> {
+    entries: &'d [u8],
+    /// (entry offset u32, binary offset u32) per restart, both LE
+    restarts: &'d [u8],
+}
+
+impl<'d> Level2Group<'d> {
+    fn parse(
+        decompressed: &'d [u8],
+        has_restarts: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if !has_restarts {
+            return Ok(Level2Group {
+                entries: decompressed,
+                restarts: &[],
+            });
+        }
+        // entries | restarts (entry offset u32, binary offset u32) | count u32
+        let count_pos = decompressed
+            .len()
+            .checked_sub(4)
+            .ok_or(MALFORMED_RESTARTS)?;
+        let count = u32::from_le_bytes(decompressed[count_pos..].try_into().unwrap()) as usize;
+        let entries_end = count
+            .checked_mul(8)
+            .and_then(|n| count_pos.checked_sub(n))
+            .ok_or(MALFORMED_RESTARTS)?;
+        let (entries, restarts) = decompressed[..count_pos].split_at(entries_end);
+        Ok(Level2Group { entries, restarts })
+    }
+
+    fn restart_count(&self) -> usize {
+        self.restarts.len() / 8
+    }
 
+    /// Entry offset and binary offset of restart `i`
+    fn restart(&self, i: usize) -> (usize, u64) {
+        let r = &self.restarts[i * 8..i * 8 + 8];
+        (
+            u32::from_le_bytes(r[..4].try_into().unwrap()) as usize,
+            u32::from_le_bytes(r[4..].try_into().unwrap()) as u64,
+        )
+    }
+}
+
+/// Decodes Level 2 entries one by one from `pos` on, which must hold a full
+/// key (the start of a group, or a restart point).
+struct Level2Cursor<'d> {
+    entries: &'d [u8],
+    pos: usize,
+    binary_offset: u64,
+    /// Key of the last decoded entry
+    word: Vec<u8>,
+}
+
+impl<'d> Level2Cursor<'d> {
+    fn new(entries: &'d [u8], pos: usize, binary_offset: u64) -> Self {
+        Level2Cursor {
+            entries,
+            pos,
+            binary_offset,
+            word: Vec::with_capacity(128),
+        }
+    }
+
+    /// Decodes the next entry's key into `word`, returning its binary offset,
+    /// relative to the group, and size. `None` at the end of the entries.
+    fn next_entry(&mut self) -> Result<Option<(u64, u64)>, Box<dyn std::error::Error>> {
+        let entries = self.entries;
+        let mut pos = self.pos;
         // entry size = 1u8 shlen + 1u8 suffixlen + 1 byte suffix + 1 byte entrylen
         let minimal_entry_size = 4;
-        while pos + minimal_entry_size <= decompressed.len() {
-            let shared_len = decompressed[pos] as usize;
-            pos += 1;
+        if pos + minimal_entry_size > entries.len() {
+            return Ok(None);
+        }
+        let shared_len = entries[pos] as usize;
+        pos += 1;
 
-            let suffix_len = decompressed[pos] as usize;
-            pos += 1;
+        let suffix_len = entries[pos] as usize;
+        pos += 1;
 
-            if pos + suffix_len + 2 > decompressed.len() {
-                // TODO: why does this happen? eg: Holzz in eng dict
-                return Ok(None);
-                //panic!(
-                //    "malformed data, expected suffix but got EOF, pos {} shlen {} suflen {}",
-                //    pos, shared_len, suffix_len
-                //);
-            }
+        // suffix + at least 1 byte of binary data size
+        if pos + suffix_len + 1 > entries.len() {
+            return Err("malformed level 2 group, unexpected EOF".into());
+        }
+
+        // Keys are unique, so each one adds at least a byte
+        if suffix_len == 0 {
+            return Err("malformed level 2 group, empty suffix".into());
+        }
+        let suffix_b = &entries[pos..pos + suffix_len];
+        pos += suffix_len;
 
-            assert!(suffix_len > 0);
-            let suffix_b = &decompressed[pos..pos + suffix_len];
-            pos += suffix_len;
+        if shared_len > self.word.len() {
+            return Err("malformed level 2 group, shared prefix too long".into());
+        }
+        self.word.truncate(shared_len);
+        self.word.extend_from_slice(suffix_b);
+
+        let mut size_bytes = &entries[pos..];
+        let binary_size = VarU64::deserialize(&mut size_bytes)?.0;
+        self.pos = entries.len() - size_bytes.len();
+
+        let binary_offset = self.binary_offset;
+        self.binary_offset += binary_size;
+        Ok(Some((binary_offset, binary_size)))
+    }
+}
 
-            if shared_len > 0 {
-                assert!(!current_word.is_empty());
-                current_word.truncate(shared_len);
+/// Decodes Level 2 entries from `pos` on, which must hold a full key (the
+/// start of a group, or a restart point), until reaching `wanted_word_b`.
+/// Returns where its binary data is, and how many entries came before it
+/// from `pos`.
+fn scan_level2_entries(
+    entries: &[u8],
+    pos: usize,
+    binary_offset: u64,
+    wanted_word_b: &[u8],
+) -> Result<Option<GroupEntry>, Box<dyn std::error::Error>> {
+    let mut cursor = Level2Cursor::new(entries, pos, binary_offset);
+    let mut skipped = 0;
+    while let Some((binary_offset, binary_size)) = cursor.next_entry()? {
+        match cursor.word.as_slice().cmp(wanted_word_b) {
+            std::cmp::Ordering::Equal => {
+                return Ok(Some(GroupEntry {
+                    binary_offset,
+                    binary_size,
+                    position: skipped,
+                }));
             }
-            current_word.extend_from_slice(suffix_b);
-
-            // Read binary data size VarUint
-            // TODO: use VarUint instead of duplicated logic
-            let maybe_size_bytes = &decompressed[pos..pos + 2];
-            pos += 1;
-            let first_byte = maybe_size_bytes[0];
-            let second_byte = maybe_size_bytes[1];
-            let binary_size = if (first_byte & 0x80) == 0x80 {
-                pos += 1;
-                ((first_byte & 0x7F) as u16) | ((second_byte as u16) << 7)
-            } else {
-                first_byte as u16
+            std::cmp::Ordering::Greater => return Ok(None),
+            std::cmp::Ordering::Less => skipped += 1,
+        }
+    }
+    Ok(None)
+}
+
+/// count | (key | size | data) per entry
+fn parse_hot_frame(mut b: &[u8]) -> Result<HashMap<String, Vec<u8>>, Box<dyn std::error::Error>> {
+    let count = VarU64::deserialize(&mut b)?.0 as usize;
+    let mut hot = HashMap::with_capacity(count.min(1 << 16));
+    for _ in 0..count {
+        let key = String::deserialize(&mut b)?;
+        let size = VarU64::deserialize(&mut b)?.0 as usize;
+        if size > b.len() {
+            return Err("Truncated hot frame".into());
+        }
+        let (data, rest) = b.split_at(size);
+        hot.insert(key, data.to_vec());
+        b = rest;
+    }
+    Ok(hot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{inflected, mono_words, open_dict, plural_forms, tagged_words};
+    use crate::writer::{WriterOptions, build_forms_index};
+    use crate::{LinkKind, PartOfSpeech, SenseLink, WordTag};
+
+    #[test]
+    fn test_forms_fallback() {
+        let tagged_words = mono_words(&["perro", "ser"]);
+        let forms = build_forms_index(
+            vec![
+                inflected("perros", "perro", &["plural"]),
+                inflected("fue", "ser", &["preterite", "third-person"]),
+                inflected("fue", "ir", &["preterite", "third-person"]),
+            ],
+            &tagged_words,
+        );
+        // "ir" is not a headword
+        assert_eq!(forms.len(), 2);
+
+        let mut dict_reader = open_dict(&tagged_words, &forms, &WriterOptions::default());
+        assert!(dict_reader.has_forms_index());
+
+        assert!(dict_reader.lookup_exact("perros").unwrap().is_none());
+        let word = dict_reader.lookup("perros").unwrap().unwrap();
+        assert_eq!(word.word, "perro");
+        assert_eq!(
+            dict_reader.lookup_forms("perros").unwrap(),
+            vec![FormOf {
+                lemma: "perro".to_string(),
+                tags: vec!["plural".to_string()],
+            }]
+        );
+        assert_eq!(dict_reader.lookup("fue").unwrap().unwrap().word, "ser");
+        assert!(dict_reader.lookup("perras").unwrap().is_none());
+    }
+
+    // Parses Kaikki JSON
+    #[test]
+    #[cfg(feature = "indexer")]
+    fn test_sense_links() {
+        let tagged_words = crate::test_util::kaikki_words(&[
+            r#"{"word": "perro", "pos": "noun", "senses": [{"glosses": ["dog"]}]}"#,
+            r#"{"word": "perros", "pos": "noun", "senses": [{"glosses": ["plural of perro"], "form_of": [{"word": "perro"}]}]}"#,
+            r#"{"word": "perr", "pos": "noun", "senses": [{"glosses": ["misspelling of perro"], "alt_of": [{"word": "perro"}, {"word": "gato"}]}]}"#,
+        ]);
+        let mut dict_reader = open_dict(&tagged_words, &[], &WriterOptions::default());
+
+        let perros = dict_reader.lookup("perros").unwrap().unwrap();
+        assert_eq!(
+            perros.entries[0].senses[0].links,
+            vec![SenseLink {
+                kind: LinkKind::FormOf,
+                target: "perro".to_string(),
+            }]
+        );
+        let resolved = dict_reader.resolve_links(&perros).unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].word, "perro");
+        assert_eq!(
+            resolved[0].entries[0].senses[0].glosses[0].gloss_lines,
+            ["dog"]
+        );
+
+        // "gato" is not in the dictionary. Links are sorted by kind and
+        // target.
+        let perr = dict_reader.lookup("perr").unwrap().unwrap();
+        assert_eq!(perr.link_targets(), ["gato", "perro"]);
+        assert_eq!(perr.entries[0].senses[0].links[1].kind, LinkKind::AltOf);
+        assert_eq!(dict_reader.resolve_links(&perr).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_word_ids() {
+        let words: Vec<String> = (0..100).map(|i| format!("palabra{}", i)).collect();
+        let tagged_words = mono_words(&words);
+
+        let mut build_ids = Vec::new();
+        for mphf in [false, true] {
+            let opts = WriterOptions {
+                mphf,
+                restart_interval: 8,
+                ..Default::default()
             };
+            let mut dict_reader = open_dict(&tagged_words, &[], &opts);
+            build_ids.push(dict_reader.build_id().unwrap());
 
-            if current_word == wanted_word_b {
-                return Ok(Some((binary_offset, binary_size)));
+            // Ids follow the sorted key order
+            let mut sorted = words.clone();
+            sorted.sort();
+            for (id, w) in sorted.iter().enumerate() {
+                assert_eq!(dict_reader.lookup(w).unwrap().unwrap().id, Some(id as u32));
+                let found = dict_reader.lookup_by_id(id as u32).unwrap().unwrap();
+                assert_eq!(&found.word, w);
+                assert_eq!(found.entries[0].senses[0].glosses[0].gloss_lines[0], *w);
             }
+            assert!(dict_reader.lookup_by_id(100).unwrap().is_none());
+        }
+        assert_eq!(build_ids[0], build_ids[1]);
 
-            if current_word.as_slice() > wanted_word_b {
-                return Ok(None);
+        // Adding a word shifts the ids, so the build id changes
+        let mut more_words = words.clone();
+        more_words.push("palabra".to_string());
+        let opts = WriterOptions::default();
+        let dict_reader = open_dict(&mono_words(&more_words), &[], &opts);
+        assert_ne!(dict_reader.build_id().unwrap(), build_ids[0]);
+    }
+
+    // Parses Kaikki JSON
+    #[test]
+    #[cfg(feature = "indexer")]
+    fn test_sense_ids() {
+        use crate::GlossLine;
+        let build = |kaikki_words: &[&str]| {
+            let tagged_words = crate::test_util::kaikki_words(kaikki_words);
+            open_dict(&tagged_words, &[], &WriterOptions::default())
+        };
+        let banco_verb = r#"{"word": "banco", "pos": "verb", "senses": [{"glosses": ["first-person singular of bancar"]}]}"#;
+        let mut old = build(&[
+            r#"{"word": "banco", "pos": "noun", "senses": [{"glosses": ["bench"]}, {"glosses": ["bank"]}]}"#,
+            banco_verb,
+        ]);
+        let mut new = build(&[
+            r#"{"word": "banca", "pos": "noun", "senses": [{"glosses": ["banking"]}]}"#,
+            r#"{"word": "banco", "pos": "noun", "senses": [{"glosses": ["long seat"]}, {"glosses": ["bank"]}]}"#,
+            banco_verb,
+        ]);
+
+        let gloss_ids = |w: &WordWithTaggedEntries| -> Vec<(u64, u64, GlossLine)> {
+            w.senses_with_ids()
+                .flat_map(|(sense_id, s)| {
+                    let sense_key = s.key(&w.word, WordTag::Monolingual);
+                    (s.glosses.iter())
+                        .map(move |g| (sense_id, g.id(sense_key), g.gloss_lines[0].clone()))
+                })
+                .collect()
+        };
+        let old_ids = gloss_ids(&old.lookup("banco").unwrap().unwrap());
+        let new_ids = gloss_ids(&new.lookup("banco").unwrap().unwrap());
+        assert_eq!(old_ids.len(), 3);
+        assert_eq!(new_ids.len(), 3);
+        // Unchanged glosses keep their ids, the edited one gets a new one,
+        // and so does the sense it's in
+        for (old_id, new_id) in old_ids.iter().zip(&new_ids) {
+            assert_eq!(old_id.1 == new_id.1, old_id.2 == new_id.2);
+        }
+        assert_ne!(old_ids[0].0, new_ids[0].0);
+        assert_eq!(old_ids[2].0, new_ids[2].0);
+        assert_eq!(old_ids[2].2, "first-person singular of bancar");
+
+        let (_, bank_id, _) = old_ids.iter().find(|g| g.2 == "bank").unwrap();
+        assert!(new.lookup_sense("banco", old_ids[0].0).unwrap().is_none());
+        let noun = new.lookup_sense("banco", new_ids[0].0).unwrap().unwrap();
+        assert_eq!(noun.pos, PartOfSpeech::Noun);
+        assert_eq!(noun.glosses.len(), 2);
+        let bank = new.lookup_sense("banco", *bank_id).unwrap().unwrap();
+        assert_eq!(bank.glosses.len(), 1);
+        assert_eq!(bank.glosses[0].gloss_lines, ["bank"]);
+        assert!(new.lookup_sense("banca", *bank_id).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_nfc_normalized_keys() {
+        let nfc = "caf\u{e9}";
+        let nfd = "cafe\u{301}";
+        let tagged_words =
+            tagged_words(&[(nfd, "noun", "coffee", true), (nfc, "noun", "cafe", false)]);
+        // Both spellings are the same word
+        assert_eq!(tagged_words.len(), 1);
+        assert_eq!(tagged_words[0].word, nfc);
+        assert!(matches!(tagged_words[0].tag, WordTag::Both));
+
+        let mut dict_reader = open_dict(&tagged_words, &[], &WriterOptions::default());
+        assert_eq!(dict_reader.normalization(), Normalization::Nfc);
+        for query in [nfc, nfd] {
+            let word = dict_reader.lookup(query).unwrap().unwrap();
+            assert_eq!(word.word, nfc);
+        }
+    }
+
+    #[test]
+    fn test_restart_points() {
+        // A single Prefix3 group of 300 entries
+        let words: Vec<String> = (0..300).map(|i| format!("con{:03}", i)).collect();
+        let tagged_words = mono_words(&words);
+
+        for restart_interval in [0, 1, 7] {
+            let opts = WriterOptions {
+                grouping: Grouping::Prefix3,
+                restart_interval,
+                ..Default::default()
+            };
+            let mut dict_reader = open_dict(&tagged_words, &[], &opts);
+            for w in &words {
+                let found = dict_reader.lookup(w).unwrap().expect("word missing");
+                assert_eq!(&found.word, w);
+            }
+            for missing in ["co", "con", "con0", "con0000", "con05", "con299a", "con3"] {
+                assert!(dict_reader.lookup(missing).unwrap().is_none());
             }
+        }
+    }
 
-            binary_offset += binary_size as u32;
+    #[test]
+    fn test_mphf_lookups() {
+        let words: Vec<String> = (0..500).map(|i| format!("palabra{}", i)).collect();
+        let tagged_words = mono_words(&words);
+
+        let opts = WriterOptions {
+            mphf: true,
+            ..Default::default()
+        };
+        let mut dict_reader = open_dict(&tagged_words, &[], &opts);
+        assert!(dict_reader.has_mphf());
+        for w in &words {
+            let found = dict_reader.lookup_exact(w).unwrap().expect("word missing");
+            assert_eq!(found.entries[0].senses[0].glosses[0].gloss_lines[0], *w);
+        }
+        for missing in ["palabra", "palabra500", "palabra1x"] {
+            assert!(dict_reader.lookup_exact(missing).unwrap().is_none());
         }
+    }
 
-        Ok(None)
+    #[test]
+    fn test_fst_queries() {
+        let words = ["cama", "camas", "casa", "cosa", "gato", "perro", "perros"];
+        let tagged_words = mono_words(&words);
+
+        let dict_reader = open_dict(&tagged_words, &[], &WriterOptions::default());
+        assert!(!dict_reader.has_fst());
+        assert!(dict_reader.count_prefix("ca").is_err());
+
+        let opts = WriterOptions {
+            grouping: Grouping::Prefix3,
+            fst: true,
+            ..Default::default()
+        };
+        let dict_reader = open_dict(&tagged_words, &[], &opts);
+        let keys =
+            |found: Vec<(String, u64)>| found.into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+
+        assert_eq!(
+            keys(dict_reader.search_regex("ca.a").unwrap()),
+            ["cama", "casa"]
+        );
+        assert_eq!(
+            keys(dict_reader.search_fuzzy("caza", 1).unwrap()),
+            ["cama", "casa"]
+        );
+        assert_eq!(
+            keys(dict_reader.search_range("cam", "d").unwrap()),
+            ["cama", "camas", "casa", "cosa"]
+        );
+        assert_eq!(dict_reader.count_prefix("perro").unwrap(), 2);
+        assert_eq!(dict_reader.count_prefix("z").unwrap(), 0);
+        // Ordinals are positions in the index
+        let ordinals: Vec<u64> = dict_reader
+            .search_range("", "\u{10FFFF}")
+            .unwrap()
+            .into_iter()
+            .map(|(_, o)| o)
+            .collect();
+        assert_eq!(ordinals, (0..words.len() as u64).collect::<Vec<_>>());
     }
 
-    fn get_word_from_binary_data(
-        &mut self,
-        offset: u32,
-        size: u16,
-        word: &str,
-    ) -> Result<WordWithTaggedEntries, Box<dyn std::error::Error>> {
-        self.decoder.set_offset(offset as u64)?;
-        self.decoder.set_offset_limit(offset as u64 + size as u64)?;
-
-        let mut decompressed = Vec::with_capacity(32768);
-        std::io::copy(&mut self.decoder, &mut decompressed)?;
-        let parsed = WordWithTaggedEntries::named_deserialize(
-            &mut decompressed.as_slice(),
-            word.to_string(),
-        )
-        .map_err(|e| -> Box<dyn std::error::Error> { Box::from(e) })?;
+    #[test]
+    fn test_bloom_filter() {
+        let words: Vec<String> = (0..200).map(|i| format!("perro{}", i)).collect();
+        let tagged_words = mono_words(&words);
+        let plurals: Vec<(String, &str)> = (words.iter())
+            .map(|w| (format!("{}s", w), w.as_str()))
+            .collect();
+        let forms = plural_forms(&plurals, &tagged_words);
 
-        Ok(parsed)
+        let opts = WriterOptions {
+            bloom_bits_per_key: 10,
+            ..Default::default()
+        };
+        let mut dict_reader = open_dict(&tagged_words, &forms, &opts);
+        assert!(dict_reader.has_bloom_filter());
+        for w in &words {
+            assert!(dict_reader.contains(w).unwrap());
+            assert!(dict_reader.contains(&format!("{}s", w)).unwrap());
+            assert_eq!(
+                &dict_reader
+                    .lookup(&format!("{}s", w))
+                    .unwrap()
+                    .unwrap()
+                    .word,
+                w
+            );
+        }
+        for i in 0..200 {
+            assert!(!dict_reader.contains(&format!("gato{}", i)).unwrap());
+            assert!(
+                dict_reader
+                    .lookup(&format!("perro{}x", i))
+                    .unwrap()
+                    .is_none()
+            );
+        }
+    }
+
+    #[test]
+    fn test_hot_words() {
+        let words: Vec<String> = (0..100).map(|i| format!("talo{}", i)).collect();
+        let tagged_words = mono_words(&words);
+        let forms = plural_forms(&[("talo7t", "talo7")], &tagged_words);
+
+        // An inflected form counts for its lemma, unknown words are skipped
+        let frequency_list = ["talo3", "talo7t", "missing", "talo50", "talo99"];
+        for codec in [Codec::Zstd, Codec::Lz4] {
+            let opts = WriterOptions {
+                codec,
+                mphf: codec == Codec::Lz4,
+                ..Default::default()
+            };
+            let mut cold_reader = open_dict(&tagged_words, &forms, &opts);
+            let hot_opts = WriterOptions {
+                frequency_list: frequency_list.iter().map(|w| w.to_string()).collect(),
+                hot_words: 3,
+                ..opts
+            };
+            let mut hot_reader = open_dict(&tagged_words, &forms, &hot_opts);
+            assert_eq!(cold_reader.hot_word_count(), 0);
+            assert_eq!(hot_reader.hot_word_count(), 3);
+            for w in words.iter().chain([&"talo7t".to_string()]) {
+                let expected = cold_reader.lookup(w).unwrap();
+                let found = hot_reader.lookup(w).unwrap();
+                assert_eq!(format!("{:?}", found), format!("{:?}", expected));
+                let id = found.unwrap().id.unwrap();
+                assert_eq!(
+                    format!("{:?}", hot_reader.lookup_by_id(id).unwrap()),
+                    format!("{:?}", cold_reader.lookup_by_id(id).unwrap())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_signature() {
+        use crate::test_util::write_dict;
+        use std::io::Cursor;
+        let tagged_words = mono_words(&["casa", "perro"]);
+        let key = [7u8; 32];
+        let public_key = signature::public_key(&key);
+
+        let mut dict_reader = open_dict(&tagged_words, &[], &WriterOptions::default());
+        assert!(!dict_reader.is_signed());
+        assert!(dict_reader.verify_signature(&public_key).is_err());
+
+        let opts = WriterOptions {
+            signing_key: Some(key),
+            ..Default::default()
+        };
+        let mut buffer = write_dict(&tagged_words, &[], &opts);
+        let mut dict_reader = DictionaryReader::open(Cursor::new(buffer.clone())).unwrap();
+        assert!(dict_reader.lookup("casa").unwrap().is_some());
+        assert!(dict_reader.verify_signature(&public_key).unwrap());
+        assert!(
+            !dict_reader
+                .verify_signature(&signature::public_key(&[8; 32]))
+                .unwrap()
+        );
+        // Lookups still work after reading the whole file
+        assert!(dict_reader.lookup("perro").unwrap().is_some());
+
+        // Tampered timestamp
+        buffer[16] ^= 1;
+        let mut dict_reader = DictionaryReader::open(Cursor::new(buffer)).unwrap();
+        assert!(!dict_reader.verify_signature(&public_key).unwrap());
     }
 }
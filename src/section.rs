@@ -0,0 +1,75 @@
+use std::io::{self, Write};
+
+/// Bloom filter of the words and forms, see `BloomFilter::to_bytes`
+pub const SECTION_BLOOM: [u8; 4] = *b"BLOM";
+/// Compression settings of the stream, see `Compression::to_bytes`
+pub const SECTION_COMPRESSION: [u8; 4] = *b"CMPR";
+/// FST of the words -> their ordinals, for regex, fuzzy and range queries
+pub const SECTION_FST: [u8; 4] = *b"FSTM";
+/// Inflected form -> lemma index
+pub const SECTION_FORMS: [u8; 4] = *b"FORM";
+/// Level 1 grouping strategy, see `Grouping::to_bytes`
+pub const SECTION_GROUPING: [u8; 4] = *b"GRUP";
+/// Decompressed size (8 B LE) + the hot frame, compressed with the file's
+/// codec: the most frequent words' keys and entries, which have a 0 size in
+/// Level 2
+pub const SECTION_HOT_WORDS: [u8; 4] = *b"HOTW";
+/// Stream layout of the Level 2 groups and binary data, 1 byte, see
+/// `StreamLayout`
+pub const SECTION_LAYOUT: [u8; 4] = *b"LAYT";
+/// Minimal perfect hash of the words, see `MphfIndex::to_bytes`
+pub const SECTION_MPHF: [u8; 4] = *b"MPHF";
+/// Offsets encoding (1 B) + size of the words' Level 2 groups (8 B LE),
+/// which may not fit the header
+pub const SECTION_OFFSETS: [u8; 4] = *b"OFFS";
+/// Build id (8 B LE) + ordinal of each words' Level 1 group's first entry
+/// (4 B LE each). The build id is derived from the keys in index order, so
+/// it changes whenever the ordinals may have.
+pub const SECTION_ORDINALS: [u8; 4] = *b"ORDN";
+/// Restart interval of the Level 2 groups (4 B LE), see `WriterOptions`
+pub const SECTION_RESTARTS: [u8; 4] = *b"RSTR";
+/// ed25519 signature (64 B) of the file, see `signature::file_digest`.
+/// Written last, so it ends the section directory.
+pub const SECTION_SIGNATURE: [u8; 4] = *b"SIGN";
+/// Interned gloss lines, see `StringTable::to_bytes`
+pub const SECTION_STRINGS: [u8; 4] = *b"STRS";
+/// Unicode normalization form of the keys, 1 byte
+pub const SECTION_NORMALIZATION: [u8; 4] = *b"NORM";
+
+/// An entry of the section directory, which sits between Level 1 and the
+/// compressed data. Each entry is `tag (4 B) | len (4 B LE) | payload`.
+pub struct Section {
+    pub tag: [u8; 4],
+    /// Where the payload starts in the section directory
+    pub offset: usize,
+    pub payload: Vec<u8>,
+}
+
+pub fn write_section<W: Write>(w: &mut W, tag: [u8; 4], payload: &[u8]) -> io::Result<usize> {
+    w.write_all(&tag)?;
+    w.write_all(&(payload.len() as u32).to_le_bytes())?;
+    w.write_all(payload)?;
+    Ok(8 + payload.len())
+}
+
+pub fn parse_sections(directory: &[u8]) -> Result<Vec<Section>, Box<dyn std::error::Error>> {
+    let mut sections = Vec::new();
+    let mut data = directory;
+    while !data.is_empty() {
+        if data.len() < 8 {
+            return Err("Truncated section directory".into());
+        }
+        let tag = [data[0], data[1], data[2], data[3]];
+        let len = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+        if data.len() < 8 + len {
+            return Err(format!("Section {} is truncated", String::from_utf8_lossy(&tag)).into());
+        }
+        sections.push(Section {
+            tag,
+            offset: directory.len() - data.len() + 8,
+            payload: data[8..8 + len].to_vec(),
+        });
+        data = &data[8 + len..];
+    }
+    Ok(sections)
+}
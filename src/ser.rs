@@ -1,10 +1,29 @@
+use std::fmt;
 use std::io::{self, Write};
 
 #[derive(Debug)]
 pub enum SerializeError {
     IoError(io::Error),
+    /// A string or list is longer than its length prefix can hold
+    TooLong {
+        len: usize,
+        max: usize,
+    },
+}
+
+impl fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerializeError::IoError(err) => write!(f, "IO error: {}", err),
+            SerializeError::TooLong { len, max } => {
+                write!(f, "Length {} is over the maximum of {}", len, max)
+            }
+        }
+    }
 }
 
+impl std::error::Error for SerializeError {}
+
 impl From<io::Error> for SerializeError {
     fn from(err: io::Error) -> Self {
         SerializeError::IoError(err)
@@ -16,6 +35,8 @@ pub enum MaxLen {
     OneByte,
     TwoBytes,
     TwoBytesVar,
+    /// `VarU64` count, no practical limit
+    Var,
 }
 
 pub trait CompactSerialize {
@@ -58,19 +79,23 @@ impl<T: CompactSerialize> CompactSerializeWithMaxLen for &[T] {
         let mut size = 0;
         match max_len {
             MaxLen::OneByte => {
-                assert!(self.len() <= u8::MAX as usize);
+                check_len(self.len(), u8::MAX as usize)?;
                 size += 1;
                 out.write_all(&[self.len() as u8])?;
             }
             MaxLen::TwoBytes => {
-                assert!(self.len() <= u16::MAX as usize);
+                check_len(self.len(), u16::MAX as usize)?;
                 size += 2;
                 out.write_all(&(self.len() as u16).to_le_bytes())?;
             }
             MaxLen::TwoBytesVar => {
+                check_len(self.len(), VarUint::MAX)?;
                 let vu: VarUint = self.len().into();
                 size += vu.serialize(out)?;
             }
+            MaxLen::Var => {
+                size += VarU64(self.len() as u64).serialize(out)?;
+            }
         }
         for item in self.iter() {
             size += item.serialize(out)?;
@@ -82,6 +107,7 @@ impl<T: CompactSerialize> CompactSerializeWithMaxLen for &[T] {
 impl CompactSerialize for String {
     // TODO: this forces varint str
     fn serialize<W: Write>(&self, out: &mut W) -> Result<usize, SerializeError> {
+        check_len(self.len(), VarUint::MAX)?;
         let vu: VarUint = self.len().into();
         vu.serialize(out)?;
         let b = self.as_bytes();
@@ -102,8 +128,18 @@ impl CompactSerialize for Option<String> {
 
 pub struct VarUint(u16);
 
+fn check_len(len: usize, max: usize) -> Result<(), SerializeError> {
+    if len > max {
+        return Err(SerializeError::TooLong { len, max });
+    }
+    Ok(())
+}
+
 impl VarUint {
-    fn serialized_len(&self) -> usize {
+    /// Largest value that fits in two bytes
+    pub const MAX: usize = (u16::MAX / 2) as usize - 1;
+
+    pub fn serialized_len(&self) -> usize {
         if self.0 <= 127 {
             return 1;
         }
@@ -142,6 +178,28 @@ impl CompactSerialize for VarUint {
         Ok(self.serialized_len())
     }
 }
+
+/// LEB128: 7 bits per byte, low bits first, high bit set on all but the
+/// last byte
+pub struct VarU64(pub u64);
+
+impl VarU64 {
+    pub fn serialized_len(&self) -> usize {
+        (64 - self.0.leading_zeros() as usize).max(1).div_ceil(7)
+    }
+}
+
+impl CompactSerialize for VarU64 {
+    fn serialize<W: Write>(&self, out: &mut W) -> Result<usize, SerializeError> {
+        let mut v = self.0;
+        while v >= 0x80 {
+            out.write_all(&[(v as u8) | 0x80])?;
+            v >>= 7;
+        }
+        out.write_all(&[v as u8])?;
+        Ok(self.serialized_len())
+    }
+}
 pub use tarkka_derive::CompactSerialize;
 
 impl<T> CompactSerialize for &T
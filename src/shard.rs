@@ -0,0 +1,389 @@
+use crate::reader::DictionaryReader;
+use crate::{Sense, TARKKA_FMT_VERSION, WordWithTaggedEntries, nfc_key};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST_MAGIC: &[u8; 4] = b"SHRD";
+
+#[derive(Debug, Clone)]
+pub struct ShardInfo {
+    /// A shard covers every key from its `first_key` (in byte order) up to
+    /// the next shard's: the shortest prefix of its first word that sorts
+    /// after the previous shard's last word, and "" for the first shard
+    pub first_key: String,
+    pub word_count: u32,
+    /// File name, relative to the manifest's directory
+    pub filename: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShardManifest {
+    pub timestamp_s: u64,
+    pub word_count: u32,
+    pub shards: Vec<ShardInfo>,
+}
+
+impl ShardManifest {
+    /// Fails with `InvalidInput` over 65535 shards, or for a first key or
+    /// a filename over 255 bytes, which the manifest can't hold
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        let shard_count = u16::try_from(self.shards.len())
+            .map_err(|_| invalid(format!("{} shards, over 65535", self.shards.len())))?;
+        for shard in &self.shards {
+            for s in [&shard.first_key, &shard.filename] {
+                if s.len() > u8::MAX as usize {
+                    return Err(invalid(format!("{} is over 255 bytes", s)));
+                }
+            }
+        }
+        w.write_all(MANIFEST_MAGIC)?;
+        w.write_all(&[TARKKA_FMT_VERSION])?;
+        w.write_all(&self.timestamp_s.to_le_bytes())?;
+        w.write_all(&self.word_count.to_le_bytes())?;
+        w.write_all(&shard_count.to_le_bytes())?;
+        for shard in &self.shards {
+            w.write_all(&[shard.first_key.len() as u8])?;
+            w.write_all(shard.first_key.as_bytes())?;
+            w.write_all(&shard.word_count.to_le_bytes())?;
+            w.write_all(&[shard.filename.len() as u8])?;
+            w.write_all(shard.filename.as_bytes())?;
+        }
+        w.flush()
+    }
+
+    pub fn read<R: Read>(mut r: R) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MANIFEST_MAGIC {
+            return Err("Invalid shard manifest format".into());
+        }
+
+        let mut ver_buf = [0u8; 1];
+        r.read_exact(&mut ver_buf)?;
+        if ver_buf[0] != TARKKA_FMT_VERSION {
+            return Err(format!(
+                "Unsupported version {}, only support {}",
+                ver_buf[0], TARKKA_FMT_VERSION
+            )
+            .into());
+        }
+
+        let mut ts_buf = [0u8; 8];
+        r.read_exact(&mut ts_buf)?;
+        let mut u32_buf = [0u8; 4];
+        r.read_exact(&mut u32_buf)?;
+        let word_count = u32::from_le_bytes(u32_buf);
+        let mut u16_buf = [0u8; 2];
+        r.read_exact(&mut u16_buf)?;
+        let shard_count = u16::from_le_bytes(u16_buf);
+
+        let mut shards = Vec::with_capacity(shard_count as usize);
+        for _ in 0..shard_count {
+            let mut len_buf = [0u8; 1];
+            r.read_exact(&mut len_buf)?;
+            let mut first_key = vec![0u8; len_buf[0] as usize];
+            r.read_exact(&mut first_key)?;
+            r.read_exact(&mut u32_buf)?;
+            r.read_exact(&mut len_buf)?;
+            let mut name_buf = vec![0u8; len_buf[0] as usize];
+            r.read_exact(&mut name_buf)?;
+            shards.push(ShardInfo {
+                first_key: String::from_utf8(first_key)?,
+                word_count: u32::from_le_bytes(u32_buf),
+                filename: String::from_utf8(name_buf)?,
+            });
+        }
+
+        Ok(ShardManifest {
+            timestamp_s: u64::from_le_bytes(ts_buf),
+            word_count,
+            shards,
+        })
+    }
+
+    /// Index of the shard that would contain `word` (NFC), if any.
+    pub fn shard_for(&self, word: &str) -> Option<usize> {
+        self.shards
+            .partition_point(|s| s.first_key.as_str() <= word)
+            .checked_sub(1)
+    }
+}
+
+/// Returned (boxed) by [`ShardedDictionaryReader::lookup`] when the word
+/// belongs to a shard whose file is not present.
+#[derive(Debug)]
+pub struct ShardNotInstalled {
+    pub shard: usize,
+    pub filename: String,
+}
+
+impl fmt::Display for ShardNotInstalled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Shard {} ({}) is not installed",
+            self.shard, self.filename
+        )
+    }
+}
+
+impl std::error::Error for ShardNotInstalled {}
+
+/// Reads a dictionary that was split into several shard files, routing each
+/// lookup to the shard covering the word.
+pub struct ShardedDictionaryReader<'a> {
+    dir: PathBuf,
+    manifest: ShardManifest,
+    readers: Vec<Option<DictionaryReader<'a, BufReader<File>>>>,
+}
+
+impl<'a> ShardedDictionaryReader<'a> {
+    pub fn open<P: AsRef<Path>>(manifest_path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let manifest_path = manifest_path.as_ref();
+        let manifest = ShardManifest::read(BufReader::new(File::open(manifest_path)?))?;
+        let dir = manifest_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let readers = manifest.shards.iter().map(|_| None).collect();
+        Ok(ShardedDictionaryReader {
+            dir,
+            manifest,
+            readers,
+        })
+    }
+
+    pub fn manifest(&self) -> &ShardManifest {
+        &self.manifest
+    }
+
+    pub fn word_count(&self) -> u32 {
+        self.manifest.word_count
+    }
+
+    pub fn is_installed(&self, shard: usize) -> bool {
+        self.dir
+            .join(&self.manifest.shards[shard].filename)
+            .exists()
+    }
+
+    /// Looks up `word` in the shard covering it, falling back to the forms
+    /// index like [`DictionaryReader::lookup`]. Only the forms index of the
+    /// shard covering `word` is searched, which is where
+    /// [`DictionaryWriter::write_sharded`](crate::writer::DictionaryWriter::write_sharded)
+    /// puts the form; its lemma may live in a different shard.
+    ///
+    /// `word` is normalized to NFC before routing, as shards are always
+    /// written with NFC keys.
+    pub fn lookup(
+        &mut self,
+        word: &str,
+    ) -> Result<Option<WordWithTaggedEntries>, Box<dyn std::error::Error>> {
+        let word = nfc_key(word);
+        let word = word.as_ref();
+        if let Some(w) = self.lookup_exact(word)? {
+            return Ok(Some(w));
+        }
+        let forms = match self.reader_for(word)? {
+            Some(r) => r.lookup_forms(word)?,
+            None => return Ok(None),
+        };
+        for form_of in forms {
+            if let Some(w) = self.lookup_exact(&form_of.lemma)? {
+                return Ok(Some(w));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Like [`DictionaryReader::lookup_by_id`]. Ids run across the shards in
+    /// order, so they stay valid as long as every shard's build id does.
+    pub fn lookup_by_id(
+        &mut self,
+        id: u32,
+    ) -> Result<Option<WordWithTaggedEntries>, Box<dyn std::error::Error>> {
+        let mut first_id = 0;
+        for shard in 0..self.manifest.shards.len() {
+            let word_count = self.manifest.shards[shard].word_count;
+            if id < first_id + word_count {
+                let mut word = self.shard_reader(shard)?.lookup_by_id(id - first_id)?;
+                if let Some(w) = &mut word {
+                    w.id = Some(id);
+                }
+                return Ok(word);
+            }
+            first_id += word_count;
+        }
+        Ok(None)
+    }
+
+    /// Looks up the headword `word` in the shard covering it, with its id
+    /// offset by the words in the shards before
+    fn lookup_exact(
+        &mut self,
+        word: &str,
+    ) -> Result<Option<WordWithTaggedEntries>, Box<dyn std::error::Error>> {
+        if word.is_empty() {
+            return Err("Empty word".into());
+        }
+        let shard = match self.manifest.shard_for(word) {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+        let first_id: u32 = (self.manifest.shards[..shard].iter())
+            .map(|s| s.word_count)
+            .sum();
+        let mut word = self.shard_reader(shard)?.lookup_exact(word)?;
+        if let Some(w) = &mut word {
+            w.id = w.id.map(|id| first_id + id);
+        }
+        Ok(word)
+    }
+
+    /// Like [`DictionaryReader::lookup_sense`]
+    pub fn lookup_sense(
+        &mut self,
+        word: &str,
+        id: u64,
+    ) -> Result<Option<Sense>, Box<dyn std::error::Error>> {
+        let word = nfc_key(word);
+        Ok(self.lookup_exact(&word)?.and_then(|w| w.sense_by_id(id)))
+    }
+
+    /// Like [`DictionaryReader::resolve_links`]; each target is looked up in
+    /// the shard covering it.
+    pub fn resolve_links(
+        &mut self,
+        word: &WordWithTaggedEntries,
+    ) -> Result<Vec<WordWithTaggedEntries>, Box<dyn std::error::Error>> {
+        let mut resolved = Vec::new();
+        for target in word.link_targets() {
+            if let Some(w) = self.lookup_exact(&nfc_key(target))? {
+                resolved.push(w);
+            }
+        }
+        Ok(resolved)
+    }
+
+    fn reader_for(
+        &mut self,
+        word: &str,
+    ) -> Result<Option<&mut DictionaryReader<'a, BufReader<File>>>, Box<dyn std::error::Error>>
+    {
+        if word.is_empty() {
+            return Err("Empty word".into());
+        }
+        match self.manifest.shard_for(word) {
+            Some(shard) => Ok(Some(self.shard_reader(shard)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Opens the shard's file on first use
+    fn shard_reader(
+        &mut self,
+        shard: usize,
+    ) -> Result<&mut DictionaryReader<'a, BufReader<File>>, Box<dyn std::error::Error>> {
+        if self.readers[shard].is_none() {
+            let filename = &self.manifest.shards[shard].filename;
+            let path = self.dir.join(filename);
+            if !path.exists() {
+                return Err(Box::new(ShardNotInstalled {
+                    shard,
+                    filename: filename.clone(),
+                }));
+            }
+            let r = DictionaryReader::open(BufReader::new(File::open(path)?))?;
+            self.readers[shard] = Some(r);
+        }
+
+        Ok(self.readers[shard].as_mut().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{inflected, mono_words};
+    use crate::writer::{DictionaryWriter, WriterError, build_forms_index};
+
+    #[test]
+    fn test_sharded_write_read() {
+        let words = ["cama", "casa", "dado", "de", "perro", "y"];
+        let tagged_words = mono_words(&words);
+
+        let dir = std::env::temp_dir().join(format!("tarkka-shards-{}", std::process::id()));
+        let writer = DictionaryWriter::new().timestamp(0);
+        let manifest = (writer.write_sharded(&dir, "es", &tagged_words, &[], 2)).unwrap();
+        assert_eq!(manifest.word_count, 6);
+        assert_eq!(manifest.shards.len(), 3);
+
+        let mut reader = ShardedDictionaryReader::open(dir.join("es.manifest")).unwrap();
+        for w in words {
+            let found = reader.lookup(w).unwrap().expect("word missing from shards");
+            assert_eq!(found.entries[0].senses[0].glosses[0].gloss_lines[0], w);
+        }
+        assert!(reader.lookup("gato").unwrap().is_none());
+        // Ids are global across shards
+        let mut ids = Vec::new();
+        for w in words {
+            let id = reader.lookup(w).unwrap().unwrap().id.unwrap();
+            assert_eq!(reader.lookup_by_id(id).unwrap().unwrap().word, w);
+            ids.push(id);
+        }
+        ids.sort();
+        assert_eq!(ids, [0, 1, 2, 3, 4, 5]);
+
+        let perro_shard = reader.manifest().shard_for("perro").unwrap();
+        std::fs::remove_file(dir.join(&reader.manifest().shards[perro_shard].filename)).unwrap();
+        let mut reader = ShardedDictionaryReader::open(dir.join("es.manifest")).unwrap();
+        assert!(!reader.is_installed(perro_shard));
+        let err = reader.lookup("perro").unwrap_err();
+        assert!(err.downcast_ref::<ShardNotInstalled>().is_some());
+        assert!(reader.lookup("casa").unwrap().is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sharded_forms() {
+        let tagged_words = mono_words(&["casa", "perro", "ser"]);
+        let forms = build_forms_index(
+            vec![
+                inflected("es", "ser", &[]),
+                inflected("perros", "perro", &[]),
+            ],
+            &tagged_words,
+        );
+
+        let dir = std::env::temp_dir().join(format!("tarkka-shard-forms-{}", std::process::id()));
+        let writer = DictionaryWriter::new().timestamp(0);
+        let manifest = (writer.write_sharded(&dir, "es", &tagged_words, &forms, 1)).unwrap();
+        let first_keys: Vec<&str> = (manifest.shards.iter())
+            .map(|s| s.first_key.as_str())
+            .collect();
+        assert_eq!(first_keys, ["", "p", "s"]);
+
+        // "es" is stored with "casa", in the shard covering it, and resolves
+        // to "ser" in another one
+        assert_eq!(manifest.shard_for("es"), Some(0));
+        let mut reader = ShardedDictionaryReader::open(dir.join("es.manifest")).unwrap();
+        assert_eq!(reader.lookup("es").unwrap().unwrap().word, "ser");
+        assert_eq!(reader.lookup("perros").unwrap().unwrap().word, "perro");
+
+        // What the manifest can't hold is an error, not a truncated file
+        let long_name = "x".repeat(250);
+        let err = (writer.write_sharded(&dir, &long_name, &tagged_words, &[], 1)).unwrap_err();
+        assert!(matches!(err, WriterError::TooLarge(_)));
+        assert!(!dir.join(format!("{}.0.dict", long_name)).exists());
+        let mut too_many = manifest.clone();
+        too_many.shards = vec![manifest.shards[0].clone(); 1 << 16];
+        let err = too_many.write(Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
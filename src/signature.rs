@@ -0,0 +1,54 @@
+//! Embedded ed25519 signatures. The `SIGN` section holds the signature of
+//! the file's SHA-256 digest, computed with the signature itself zeroed.
+
+use crate::patch::Sha256Digest;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::io::{self, Read};
+
+pub const SIGNATURE_LEN: usize = 64;
+
+/// SHA-256 of `r`, reading the `SIGNATURE_LEN` bytes at `signature_offset`
+/// as zeros
+pub fn file_digest<R: Read>(mut r: R, signature_offset: u64) -> io::Result<Sha256Digest> {
+    let signature = signature_offset..signature_offset + SIGNATURE_LEN as u64;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut pos = 0u64;
+    loop {
+        let n = r.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        let chunk = &mut buf[..n];
+        let start = signature.start.clamp(pos, pos + n as u64);
+        let end = signature.end.clamp(pos, pos + n as u64);
+        chunk[(start - pos) as usize..(end - pos) as usize].fill(0);
+        hasher.update(chunk);
+        pos += n as u64;
+    }
+    Ok(hasher.finalize().into())
+}
+
+/// `secret_key` is an ed25519 seed
+pub fn sign(secret_key: &[u8; 32], digest: &Sha256Digest) -> [u8; SIGNATURE_LEN] {
+    SigningKey::from_bytes(secret_key).sign(digest).to_bytes()
+}
+
+pub fn public_key(secret_key: &[u8; 32]) -> [u8; 32] {
+    SigningKey::from_bytes(secret_key)
+        .verifying_key()
+        .to_bytes()
+}
+
+pub fn verify(
+    public_key: &[u8; 32],
+    digest: &Sha256Digest,
+    signature: &[u8; SIGNATURE_LEN],
+) -> Result<bool, String> {
+    let key =
+        VerifyingKey::from_bytes(public_key).map_err(|e| format!("Invalid public key: {}", e))?;
+    Ok(key
+        .verify(digest, &Signature::from_bytes(signature))
+        .is_ok())
+}
@@ -0,0 +1,545 @@
+//! Building a `.dict` with bounded memory, see [`StreamingBuilder`].
+//!
+//! Entries are serialized as they come in and buffered up to a memory
+//! limit, then sorted and spilled to disk as a run. `finish` merges the
+//! runs into the final words, in index order, and writes them through the
+//! same encoder as [`DictionaryWriter`]: for the same input, both write the
+//! same bytes.
+
+use crate::de::{self, CompactDeserialize};
+use crate::mphf::hash64;
+use crate::ser::{CompactSerialize, VarU64};
+use crate::strings::StringTable;
+use crate::writer::{
+    BuildReport, DictionaryWriter, IndexEncoder, InflectedForm, Interned, WriterError, merge_word,
+    push_form_of, rank_hot_keys, sort_key, write_dict,
+};
+use crate::{FormEntry, FormOf, GlossLine, Grouping, WordWithTaggedEntries, nfc_key};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Tells apart the scratch files of builders sharing a directory
+static NEXT_BUILDER: AtomicUsize = AtomicUsize::new(0);
+
+/// Hash seed of the gloss line counts
+const LINE_SEED: u64 = 0x57a7_1e5e;
+
+/// Writes a `.dict` from entries added one at a time, keeping at most
+/// about `memory_limit` bytes of them in memory. The index itself (keys,
+/// Level 1 and Level 2) and the forms' data are still built in memory.
+///
+/// ```no_run
+/// # use tarkka::streaming::StreamingBuilder;
+/// # use tarkka::writer::{DictionaryWriter, WriterError};
+/// # fn main() -> Result<(), WriterError> {
+/// # let words: Vec<tarkka::WordWithTaggedEntries> = vec![];
+/// let mut builder = StreamingBuilder::new(DictionaryWriter::new(), "/tmp", 256 << 20);
+/// for word in &words {
+///     builder.add_monolingual(word)?;
+/// }
+/// builder.finish(std::fs::File::create("es.dict")?)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct StreamingBuilder {
+    writer: DictionaryWriter,
+    dir: PathBuf,
+    prefix: String,
+    words: ExternalSort,
+    forms: ExternalSort,
+}
+
+/// Which list of [`build_tagged_index`](crate::writer::build_tagged_index)
+/// an entry comes from
+const MONOLINGUAL: u8 = 0;
+const ENGLISH: u8 = 1;
+
+impl StreamingBuilder {
+    /// Spills runs to scratch files in `dir`, which are removed when done
+    pub fn new(writer: DictionaryWriter, dir: impl AsRef<Path>, memory_limit: usize) -> Self {
+        let dir = dir.as_ref().to_path_buf();
+        let prefix = format!(
+            "tarkka-{}-{}",
+            std::process::id(),
+            NEXT_BUILDER.fetch_add(1, Ordering::Relaxed)
+        );
+        let grouping = writer.opts.grouping;
+        // Forms are much smaller than words, so they get a smaller share
+        let words = ExternalSort::new(&dir, format!("{}-words", prefix), grouping, memory_limit);
+        let forms = ExternalSort::new(
+            &dir,
+            format!("{}-forms", prefix),
+            grouping,
+            memory_limit / 4,
+        );
+        StreamingBuilder {
+            writer,
+            dir,
+            prefix,
+            words,
+            forms,
+        }
+    }
+
+    /// Adds an entry from a monolingual dictionary
+    pub fn add_monolingual(&mut self, word: &WordWithTaggedEntries) -> Result<(), WriterError> {
+        self.add_word(word, MONOLINGUAL)
+    }
+
+    /// Adds an entry from an English dictionary
+    pub fn add_english(&mut self, word: &WordWithTaggedEntries) -> Result<(), WriterError> {
+        self.add_word(word, ENGLISH)
+    }
+
+    fn add_word(&mut self, word: &WordWithTaggedEntries, source: u8) -> Result<(), WriterError> {
+        let key = nfc_key(&word.word).into_owned();
+        let mut payload = vec![source];
+        if let Err(error) = word.serialize(&mut payload) {
+            return Err(WriterError::Entry { key, error });
+        }
+        self.words.push(key, payload)
+    }
+
+    /// Adds an inflected form, dropped at the end if its lemma isn't a word
+    pub fn add_form(&mut self, form: InflectedForm) -> Result<(), WriterError> {
+        let key = nfc_key(&form.form).into_owned();
+        let lemma = nfc_key(&form.lemma).into_owned();
+        let mut payload = Vec::new();
+        let form_of = FormOf {
+            lemma,
+            tags: form.tags,
+        };
+        if let Err(error) = form_of.serialize(&mut payload) {
+            return Err(WriterError::Entry { key, error });
+        }
+        self.forms.push(key, payload)
+    }
+
+    /// Merges everything added so far and writes it as a `.dict`. Returns
+    /// its sizes.
+    pub fn finish<W: Write>(self, w: W) -> Result<BuildReport, WriterError> {
+        let StreamingBuilder {
+            writer,
+            dir,
+            prefix,
+            words,
+            forms,
+        } = self;
+        let opts = &writer.opts;
+        let timestamp_s = writer.timestamp_s()?;
+        let scratch = |name: &str| TempFile::create(&dir, &format!("{}-{}", prefix, name));
+
+        // Merge the entries of each word, into a single run in index order
+        let merged = scratch("merged")?;
+        let mut headwords = HashSet::new();
+        let mut line_counts: HashMap<u64, u32> = HashMap::new();
+        {
+            let mut out = BufWriter::new(&merged.file);
+            let mut runs = words.into_merge()?;
+            while let Some((key, payloads)) = runs.next_group()? {
+                let (mut mono, mut eng) = (Vec::new(), Vec::new());
+                for payload in payloads {
+                    let (source, mut data) = payload.split_first().ok_or(WriterError::Spill(
+                        de::DeserializeError::InvalidData("Empty record"),
+                    ))?;
+                    let mut word = WordWithTaggedEntries::deserialize(&mut data)?;
+                    word.word = key.clone();
+                    match *source {
+                        MONOLINGUAL => mono.push(word),
+                        _ => eng.push(word),
+                    }
+                }
+                let word = merge_word(key.clone(), mono, eng)?;
+                if opts.string_table_min_count > 0 {
+                    for line in word.gloss_lines().filter_map(GlossLine::text) {
+                        *line_counts.entry(line_hash(line)).or_default() += 1;
+                    }
+                }
+                let mut payload = Vec::new();
+                if let Err(error) = word.serialize(&mut payload) {
+                    return Err(WriterError::Entry { key, error });
+                }
+                write_record(&mut out, &key, &payload)?;
+                headwords.insert(key);
+            }
+            out.flush()?;
+        }
+
+        // Forms whose lemma is a word, and the lemmas of those in the
+        // frequency list, to rank hot words
+        let frequent: HashSet<String> = match opts.hot_words {
+            0 => HashSet::new(),
+            _ => (opts.frequency_list.iter())
+                .map(|w| nfc_key(w).into_owned())
+                .collect(),
+        };
+        let mut frequent_forms: HashMap<String, Vec<String>> = HashMap::new();
+        let mut forms_encoder = IndexEncoder::new(opts, Vec::new());
+        let mut runs = forms.into_merge()?;
+        while let Some((key, payloads)) = runs.next_group()? {
+            let mut lemmas: Vec<FormOf> = Vec::new();
+            for payload in payloads {
+                let form_of = FormOf::deserialize(&mut payload.as_slice())?;
+                if headwords.contains(&form_of.lemma) {
+                    push_form_of(&mut lemmas, form_of);
+                }
+            }
+            if lemmas.is_empty() {
+                continue;
+            }
+            if frequent.contains(&key) {
+                let lemmas = lemmas.iter().map(|l| l.lemma.clone()).collect();
+                frequent_forms.insert(key.clone(), lemmas);
+            }
+            forms_encoder.push(&key, &FormEntry { lemmas }, false)?;
+        }
+        let forms = forms_encoder.finish()?.map_binary_data(io::Cursor::new);
+        let hot_keys = rank_hot_keys(
+            &opts.frequency_list,
+            opts.hot_words,
+            |word| headwords.contains(word),
+            |form| frequent_forms.get(form).cloned().unwrap_or_default(),
+        );
+        drop(headwords);
+
+        // Hashes can collide, so the lines counted often enough are only
+        // candidates, counted again exactly
+        let strings = match opts.string_table_min_count {
+            0 => StringTable::default(),
+            min_count => {
+                let mut counts: HashMap<String, u32> = HashMap::new();
+                for_each_word(&merged, |word| {
+                    for line in word.gloss_lines().filter_map(GlossLine::text) {
+                        if line_counts[&line_hash(line)] >= min_count {
+                            *counts.entry(line.to_string()).or_default() += 1;
+                        }
+                    }
+                    Ok(())
+                })?;
+                StringTable::from_counts(counts, min_count)
+            }
+        };
+        drop(line_counts);
+
+        let data = scratch("data")?;
+        let mut encoder = IndexEncoder::new(opts, BufWriter::new(&data.file));
+        for_each_word(&merged, |word| {
+            let hot = hot_keys.contains(&word.word);
+            let interned = Interned {
+                word: &word,
+                strings: &strings,
+            };
+            encoder.push(&word.word, &interned, hot)
+        })?;
+        let words = encoder.finish()?;
+        drop(merged);
+        let mut reader = &data.file;
+        reader.seek(SeekFrom::Start(0))?;
+        let words = words.map_binary_data(|_| BufReader::new(reader));
+
+        let stream = scratch("stream")?;
+        write_dict(w, words, forms, &strings, timestamp_s, opts, &stream.file)
+    }
+}
+
+fn line_hash(line: &str) -> u64 {
+    hash64(line.as_bytes(), LINE_SEED)
+}
+
+/// Calls `f` with each word of the merged run, in order
+fn for_each_word(
+    merged: &TempFile,
+    mut f: impl FnMut(WordWithTaggedEntries) -> Result<(), WriterError>,
+) -> Result<(), WriterError> {
+    let mut file = &merged.file;
+    file.seek(SeekFrom::Start(0))?;
+    let mut r = BufReader::new(file);
+    while let Some((key, payload)) = read_record(&mut r)? {
+        let mut word = WordWithTaggedEntries::deserialize(&mut payload.as_slice())?;
+        word.word = key;
+        f(word)?;
+    }
+    Ok(())
+}
+
+/// A scratch file, removed when dropped
+struct TempFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl TempFile {
+    fn create(dir: &Path, name: &str) -> io::Result<Self> {
+        let path = dir.join(name);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        Ok(TempFile { path, file })
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// key len (VarU64) | key | payload len (VarU64) | payload
+fn write_record<W: Write>(w: &mut W, key: &str, payload: &[u8]) -> Result<(), WriterError> {
+    VarU64(key.len() as u64).serialize(w)?;
+    w.write_all(key.as_bytes())?;
+    VarU64(payload.len() as u64).serialize(w)?;
+    w.write_all(payload)?;
+    Ok(())
+}
+
+/// `None` at the end of the run
+fn read_record<R: BufRead>(r: &mut R) -> Result<Option<(String, Vec<u8>)>, WriterError> {
+    if r.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let read_bytes = |r: &mut R| -> Result<Vec<u8>, WriterError> {
+        let len = de::VarU64::deserialize(r)?.0 as usize;
+        let mut bytes = Vec::new();
+        r.take(len as u64).read_to_end(&mut bytes)?;
+        match bytes.len() == len {
+            true => Ok(bytes),
+            false => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        }
+    };
+    let key = String::from_utf8(read_bytes(r)?)
+        .map_err(|_| de::DeserializeError::InvalidData("Invalid UTF-8 key"))?;
+    Ok(Some((key, read_bytes(r)?)))
+}
+
+/// (sort key, key, payload)
+type Record = (Vec<u8>, String, Vec<u8>);
+
+/// (sort key, run, key, payload)
+type Queued = (Vec<u8>, usize, String, Vec<u8>);
+
+/// A key and the payloads of its records
+type Group = (String, Vec<Vec<u8>>);
+
+/// Records sorted by key in index order, then in the order they were
+/// pushed, with sorted runs spilled to disk whenever the buffer is full
+struct ExternalSort {
+    dir: PathBuf,
+    prefix: String,
+    grouping: Grouping,
+    memory_limit: usize,
+    buffer: Vec<Record>,
+    buffered: usize,
+    runs: Vec<TempFile>,
+}
+
+impl ExternalSort {
+    fn new(dir: &Path, prefix: String, grouping: Grouping, memory_limit: usize) -> Self {
+        ExternalSort {
+            dir: dir.to_path_buf(),
+            prefix,
+            grouping,
+            memory_limit,
+            buffer: Vec::new(),
+            buffered: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, key: String, payload: Vec<u8>) -> Result<(), WriterError> {
+        let sort_key = sort_key(self.grouping, &key);
+        self.buffered += std::mem::size_of::<Record>() + sort_key.len() + key.len() + payload.len();
+        self.buffer.push((sort_key, key, payload));
+        if self.buffered >= self.memory_limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> Result<(), WriterError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        // Stable, so equal keys keep their order
+        self.buffer.sort_by(|a, b| a.0.cmp(&b.0));
+        let name = format!("{}.{}.run", self.prefix, self.runs.len());
+        let run = TempFile::create(&self.dir, &name)?;
+        let mut w = BufWriter::new(&run.file);
+        for (_, key, payload) in self.buffer.drain(..) {
+            write_record(&mut w, &key, &payload)?;
+        }
+        w.flush()?;
+        drop(w);
+        self.runs.push(run);
+        self.buffered = 0;
+        Ok(())
+    }
+
+    fn into_merge(mut self) -> Result<Merge, WriterError> {
+        self.spill()?;
+        let mut merge = Merge {
+            grouping: self.grouping,
+            readers: Vec::with_capacity(self.runs.len()),
+            heap: BinaryHeap::with_capacity(self.runs.len()),
+            _runs: Vec::new(),
+        };
+        for (i, run) in self.runs.iter().enumerate() {
+            let mut file = run.file.try_clone()?;
+            file.seek(SeekFrom::Start(0))?;
+            merge.readers.push(BufReader::new(file));
+            merge.refill(i)?;
+        }
+        merge._runs = std::mem::take(&mut self.runs);
+        Ok(merge)
+    }
+}
+
+/// k-way merge of sorted runs. Ties go to the earlier run, which holds the
+/// records pushed first.
+struct Merge {
+    grouping: Grouping,
+    readers: Vec<BufReader<File>>,
+    heap: BinaryHeap<Reverse<Queued>>,
+    /// Removed once merged
+    _runs: Vec<TempFile>,
+}
+
+impl Merge {
+    /// Queues the next record of run `i`, if any
+    fn refill(&mut self, i: usize) -> Result<(), WriterError> {
+        if let Some((key, payload)) = read_record(&mut self.readers[i])? {
+            let sort_key = sort_key(self.grouping, &key);
+            self.heap.push(Reverse((sort_key, i, key, payload)));
+        }
+        Ok(())
+    }
+
+    fn next_record(&mut self) -> Result<Option<(String, Vec<u8>)>, WriterError> {
+        let Some(Reverse((_, run, key, payload))) = self.heap.pop() else {
+            return Ok(None);
+        };
+        self.refill(run)?;
+        Ok(Some((key, payload)))
+    }
+
+    /// The next key, with the payloads of all its records in push order
+    fn next_group(&mut self) -> Result<Option<Group>, WriterError> {
+        let Some((key, payload)) = self.next_record()? else {
+            return Ok(None);
+        };
+        let mut payloads = vec![payload];
+        while (self.heap.peek()).is_some_and(|Reverse((_, _, next, _))| *next == key) {
+            if let Some((_, payload)) = self.next_record()? {
+                payloads.push(payload);
+            }
+        }
+        Ok(Some((key, payloads)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WordTag;
+    use crate::reader::DictionaryReader;
+    use crate::test_util::split_words;
+    use crate::writer::{InflectedForm, WriterOptions, build_forms_index, build_tagged_index};
+    use crate::{Codec, StreamLayout};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_streaming_builder() {
+        let mut test_words: Vec<(String, &str, String, bool)> = Vec::new();
+        for i in 0..200 {
+            let w = format!("sana{}", i);
+            let gloss = match i % 4 {
+                0 => format!("meaning {}", i),
+                _ => "plural of sana".to_string(),
+            };
+            test_words.push((w.clone(), "noun", gloss.clone(), true));
+            if i % 3 == 0 {
+                test_words.push((w.clone(), "verb", "to word".to_string(), false));
+            }
+            if i % 5 == 0 {
+                test_words.push((w, "verb", gloss, true));
+            }
+        }
+        // Decomposed, merged with the composed entry
+        test_words.push((
+            "cafe\u{301}".to_string(),
+            "noun",
+            "coffee".to_string(),
+            true,
+        ));
+        test_words.push(("café".to_string(), "noun", "café".to_string(), false));
+        let (mono, eng) = split_words(&test_words);
+        let inflected: Vec<InflectedForm> = (0..300)
+            .map(|i| InflectedForm {
+                form: format!("sanat{}", i % 150),
+                // Lemmas past sana199 don't exist
+                lemma: format!("sana{}", i),
+                tags: vec!["plural".to_string()],
+            })
+            .collect();
+
+        for (codec, layout) in [
+            (Codec::Zstd, StreamLayout::Split),
+            (Codec::Lz4, StreamLayout::Interleaved),
+        ] {
+            let opts = WriterOptions {
+                codec,
+                layout,
+                mphf: true,
+                string_table_min_count: 2,
+                frequency_list: ["sanat3", "sana10", "café"].map(String::from).to_vec(),
+                hot_words: 2,
+                ..Default::default()
+            };
+            let writer = DictionaryWriter::new().options(opts).timestamp(0);
+
+            let tagged_words = build_tagged_index(mono.clone(), eng.clone()).unwrap();
+            let forms = build_forms_index(inflected.clone(), &tagged_words);
+            let mut expected = Vec::new();
+            writer.write(&mut expected, &tagged_words, &forms).unwrap();
+
+            // A few hundred bytes per run: dozens of runs to merge
+            let dir = std::env::temp_dir();
+            let mut builder = StreamingBuilder::new(writer.clone(), &dir, 512);
+            for word in &mono {
+                builder.add_monolingual(word).unwrap();
+            }
+            for word in &eng {
+                builder.add_english(word).unwrap();
+            }
+            for form in inflected.clone() {
+                builder.add_form(form).unwrap();
+            }
+            let mut streamed = Vec::new();
+            let count = builder.finish(&mut streamed).unwrap();
+            assert_eq!(count.word_count as usize, tagged_words.len());
+            assert!(streamed == expected, "{:?} output differs", codec);
+
+            let mut dict_reader = DictionaryReader::open(Cursor::new(streamed)).unwrap();
+            assert_eq!(dict_reader.hot_word_count(), 2);
+            let found = dict_reader.lookup("café").unwrap().expect("word missing");
+            assert!(matches!(found.tag, WordTag::Both));
+            assert!(dict_reader.lookup("sanat7").unwrap().is_some());
+        }
+        let leftovers = std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| {
+                let name = e.file_name().to_string_lossy().into_owned();
+                name.starts_with(&format!("tarkka-{}-", std::process::id()))
+            })
+            .count();
+        assert_eq!(leftovers, 0, "scratch files left behind");
+    }
+}
@@ -0,0 +1,106 @@
+//! Shared table of gloss lines that repeat across many words ("plural of",
+//! "alternative form of", ...). An interned line is stored in the entry as
+//! a [`GlossLine::Ref`] to its id.
+
+use crate::de::{self, CompactDeserialize, DeserializeError};
+use crate::ser::{CompactSerialize, SerializeError, VarU64};
+use crate::{GlossLine, WordWithTaggedEntries};
+use std::collections::HashMap;
+
+#[derive(Debug, Default)]
+pub struct StringTable {
+    strings: Vec<String>,
+    ids: HashMap<String, u32>,
+}
+
+impl StringTable {
+    /// Interns the gloss lines of `words` that occur at least `min_count`
+    /// times, most frequent first so they get the shortest references
+    pub fn build<'a>(
+        words: impl IntoIterator<Item = &'a WordWithTaggedEntries>,
+        min_count: u32,
+    ) -> Self {
+        let mut counts: HashMap<&str, u32> = HashMap::new();
+        for word in words {
+            for line in word.gloss_lines().filter_map(GlossLine::text) {
+                *counts.entry(line).or_default() += 1;
+            }
+        }
+        Self::from_counts(counts, min_count)
+    }
+
+    /// Interns the lines counted at least `min_count` times, see
+    /// [`build`](Self::build)
+    pub(crate) fn from_counts<S: AsRef<str>>(
+        counts: impl IntoIterator<Item = (S, u32)>,
+        min_count: u32,
+    ) -> Self {
+        let mut interned: Vec<(S, u32)> = counts
+            .into_iter()
+            .filter(|(_, count)| *count >= min_count)
+            .collect();
+        interned.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.as_ref().cmp(b.0.as_ref())));
+        Self::from_strings(
+            (interned.into_iter())
+                .map(|(s, _)| s.as_ref().to_string())
+                .collect(),
+        )
+    }
+
+    fn from_strings(strings: Vec<String>) -> Self {
+        let ids = (strings.iter().enumerate())
+            .map(|(id, s)| (s.clone(), id as u32))
+            .collect();
+        StringTable { strings, ids }
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    /// `word` with its interned gloss lines replaced by references
+    pub fn intern(&self, word: &WordWithTaggedEntries) -> WordWithTaggedEntries {
+        let mut word = word.clone();
+        for line in word.gloss_lines_mut() {
+            if let Some(&id) = line.text().and_then(|text| self.ids.get(text)) {
+                *line = GlossLine::Ref(id);
+            }
+        }
+        word
+    }
+
+    /// Replaces the references in `word`'s gloss lines by their strings
+    pub fn resolve(&self, word: &mut WordWithTaggedEntries) -> Result<(), DeserializeError> {
+        for line in word.gloss_lines_mut() {
+            if let GlossLine::Ref(id) = *line {
+                let string = (self.strings.get(id as usize))
+                    .ok_or(DeserializeError::InvalidData("Unknown string table id"))?;
+                *line = GlossLine::Inline(string.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Payload of the `STRS` section: count (LEB128), then the strings
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SerializeError> {
+        let mut b = Vec::new();
+        VarU64(self.strings.len() as u64).serialize(&mut b)?;
+        for s in &self.strings {
+            s.serialize(&mut b)?;
+        }
+        Ok(b)
+    }
+
+    pub fn from_bytes(mut b: &[u8]) -> Result<Self, DeserializeError> {
+        let count = de::VarU64::deserialize(&mut b)?.0 as usize;
+        let mut strings = Vec::with_capacity(count.min(1024));
+        for _ in 0..count {
+            strings.push(String::deserialize(&mut b)?);
+        }
+        Ok(Self::from_strings(strings))
+    }
+}
@@ -0,0 +1,120 @@
+//! Fixtures shared by the tests that write a `.dict` and read it back
+
+use crate::reader::DictionaryReader;
+use crate::writer::{
+    DictionaryWriter, InflectedForm, WriterOptions, build_forms_index, build_tagged_index,
+};
+use crate::{
+    FormEntry, Gloss, PartOfSpeech, Sense, WordEntryComplete, WordTag, WordWithTaggedEntries,
+};
+use std::io::Cursor;
+
+/// An entry with a single `pos` sense and a single line gloss
+pub(crate) fn test_entry(pos: &str, gloss: &str) -> WordEntryComplete {
+    WordEntryComplete {
+        senses: vec![Sense {
+            pos: PartOfSpeech::try_from(pos).expect("invalid pos in test"),
+            links: vec![],
+            glosses: vec![Gloss {
+                gloss_lines: vec![gloss.into()],
+            }],
+        }],
+    }
+}
+
+/// `(word, pos, gloss, is_monolingual)` as the indexer reads them from
+/// Kaikki, one entry each -> (monolingual, English)
+pub(crate) fn split_words<W: AsRef<str>, G: AsRef<str>>(
+    words: &[(W, &str, G, bool)],
+) -> (Vec<WordWithTaggedEntries>, Vec<WordWithTaggedEntries>) {
+    let (mono, english): (Vec<_>, Vec<_>) = (words.iter())
+        .map(|(word, pos, gloss, is_mono)| {
+            let tag = match is_mono {
+                true => WordTag::Monolingual,
+                false => WordTag::English,
+            };
+            let word = WordWithTaggedEntries {
+                tag,
+                word: word.as_ref().to_string(),
+                id: None,
+                entries: vec![test_entry(pos, gloss.as_ref())],
+                sounds: None,
+                hyphenations: vec![],
+                redirects: vec![],
+            };
+            (word, *is_mono)
+        })
+        .partition(|(_, is_mono)| *is_mono);
+    let words = |w: Vec<(WordWithTaggedEntries, bool)>| w.into_iter().map(|(w, _)| w).collect();
+    (words(mono), words(english))
+}
+
+/// `(word, pos, gloss, is_monolingual)` as `build_tagged_index` returns them
+pub(crate) fn tagged_words<W: AsRef<str>, G: AsRef<str>>(
+    words: &[(W, &str, G, bool)],
+) -> Vec<WordWithTaggedEntries> {
+    let (mono, english) = split_words(words);
+    build_tagged_index(mono, english).unwrap()
+}
+
+/// Monolingual nouns, each glossed with itself, as `build_tagged_index`
+/// returns them
+pub(crate) fn mono_words<S: AsRef<str>>(words: &[S]) -> Vec<WordWithTaggedEntries> {
+    let words: Vec<(&str, &str, &str, bool)> = (words.iter())
+        .map(|w| (w.as_ref(), "noun", w.as_ref(), true))
+        .collect();
+    tagged_words(&words)
+}
+
+/// Kaikki JSON lines, as `build_tagged_index` returns them
+#[cfg(feature = "indexer")]
+pub(crate) fn kaikki_words(lines: &[&str]) -> Vec<WordWithTaggedEntries> {
+    use crate::kaikki::KaikkiWordEntry;
+    let mono = (lines.iter())
+        .map(|line| {
+            let entry: KaikkiWordEntry = serde_json::from_str(line).unwrap();
+            entry.to_word_entry_complete(WordTag::Monolingual)
+        })
+        .collect();
+    build_tagged_index(mono, vec![]).unwrap()
+}
+
+pub(crate) fn inflected(form: &str, lemma: &str, tags: &[&str]) -> InflectedForm {
+    InflectedForm {
+        form: form.to_string(),
+        lemma: lemma.to_string(),
+        tags: tags.iter().map(|t| t.to_string()).collect(),
+    }
+}
+
+/// `(form, lemma)` plurals, as `build_forms_index` returns them
+pub(crate) fn plural_forms<F: AsRef<str>, L: AsRef<str>>(
+    forms: &[(F, L)],
+    words: &[WordWithTaggedEntries],
+) -> Vec<(String, FormEntry)> {
+    let inflected = (forms.iter())
+        .map(|(form, lemma)| inflected(form.as_ref(), lemma.as_ref(), &["plural"]))
+        .collect();
+    build_forms_index(inflected, words)
+}
+
+/// `words` and `forms` as a `.dict` created at the epoch
+pub(crate) fn write_dict(
+    words: &[WordWithTaggedEntries],
+    forms: &[(String, FormEntry)],
+    opts: &WriterOptions,
+) -> Vec<u8> {
+    let writer = DictionaryWriter::new().options(opts.clone()).timestamp(0);
+    let mut buffer = Vec::new();
+    writer.write(&mut buffer, words, forms).unwrap();
+    buffer
+}
+
+/// A reader of [`write_dict`]'s output
+pub(crate) fn open_dict(
+    words: &[WordWithTaggedEntries],
+    forms: &[(String, FormEntry)],
+    opts: &WriterOptions,
+) -> DictionaryReader<'static, Cursor<Vec<u8>>> {
+    DictionaryReader::open(Cursor::new(write_dict(words, forms, opts))).unwrap()
+}
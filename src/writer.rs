@@ -0,0 +1,1953 @@
+//! Writing `.dict` files, see [`DictionaryWriter`].
+
+use crate::bloom::BloomFilter;
+use crate::codec::{StreamEncoder, compress_frame};
+use crate::de::DeserializeError;
+use crate::mphf::{self, MphfIndex};
+use crate::section::{
+    SECTION_BLOOM, SECTION_COMPRESSION, SECTION_FORMS, SECTION_FST, SECTION_GROUPING,
+    SECTION_HOT_WORDS, SECTION_LAYOUT, SECTION_MPHF, SECTION_NORMALIZATION, SECTION_OFFSETS,
+    SECTION_ORDINALS, SECTION_RESTARTS, SECTION_SIGNATURE, SECTION_STRINGS, write_section,
+};
+use crate::ser::{CompactSerialize, SerializeError, VarU64};
+use crate::shard::{ShardInfo, ShardManifest};
+use crate::signature::{self, SIGNATURE_LEN};
+use crate::strings::StringTable;
+use crate::{
+    Codec, Compression, FormEntry, FormOf, FrameSize, Grouping, HEADER_SIZE, Normalization,
+    Offsets, PartOfSpeech, Sense, StreamLayout, TARKKA_FMT_VERSION, WordEntryComplete, WordTag,
+    WordWithTaggedEntries, level1_key, nfc_key, separator_key,
+};
+use itertools::Itertools;
+#[cfg(feature = "indexer")]
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug)]
+pub enum WriterError {
+    Io(io::Error),
+    Serialize(SerializeError),
+    /// A word's entry couldn't be serialized, eg: a list is too long
+    Entry {
+        key: String,
+        error: SerializeError,
+    },
+    /// A key is too long for Level 2, or repeated
+    InvalidKey(String),
+    /// A size or an offset is over what the options allow, eg: a stream
+    /// over 4 GiB with `Offsets::U32`
+    TooLarge(String),
+    /// The MPHF or the FST couldn't be built
+    Index(String),
+    /// A word to aggregate has no entries
+    NoEntries(String),
+    /// A run spilled by a [`StreamingBuilder`](crate::streaming::StreamingBuilder)
+    /// couldn't be read back
+    Spill(DeserializeError),
+    /// `SOURCE_DATE_EPOCH` is set but isn't a timestamp
+    InvalidTimestamp(String),
+}
+
+impl fmt::Display for WriterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriterError::Io(err) => write!(f, "IO error: {}", err),
+            WriterError::Serialize(err) => write!(f, "Serialization error: {}", err),
+            WriterError::Entry { key, error } => write!(f, "Can't serialize {}: {}", key, error),
+            WriterError::InvalidKey(msg) => write!(f, "Invalid key: {}", msg),
+            WriterError::TooLarge(msg) => write!(f, "Too large: {}", msg),
+            WriterError::Index(msg) => write!(f, "Can't build index: {}", msg),
+            WriterError::NoEntries(word) => write!(f, "No entries for {:?}", word),
+            WriterError::Spill(err) => write!(f, "Can't read spilled run: {}", err),
+            WriterError::InvalidTimestamp(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for WriterError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WriterError::Io(err) => Some(err),
+            WriterError::Serialize(err) | WriterError::Entry { error: err, .. } => Some(err),
+            WriterError::Spill(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WriterError {
+    fn from(err: io::Error) -> Self {
+        WriterError::Io(err)
+    }
+}
+
+impl From<SerializeError> for WriterError {
+    fn from(err: SerializeError) -> Self {
+        WriterError::Serialize(err)
+    }
+}
+
+impl From<DeserializeError> for WriterError {
+    fn from(err: DeserializeError) -> Self {
+        WriterError::Spill(err)
+    }
+}
+
+/// Adds `form_of` to a form's lemmas, unless it's there already. A form with
+/// more lemmas (or a lemma with more tags) than its 1 byte count holds fails
+/// to write with [`WriterError::Entry`].
+pub(crate) fn push_form_of(lemmas: &mut Vec<FormOf>, form_of: FormOf) {
+    if !lemmas.contains(&form_of) {
+        lemmas.push(form_of);
+    }
+}
+
+/// Sizes of a written `.dict`, in bytes, to track them across builds
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "indexer", derive(Serialize))]
+pub struct BuildReport {
+    pub word_count: u32,
+    pub form_count: u32,
+    pub file_size: u64,
+    pub level1_size: u64,
+    /// The section directory, with the MPHF, FST, bloom filter, hot frame
+    /// and string table
+    pub sections_size: u64,
+    /// The words' Level 2 groups, decompressed
+    pub level2_size: u64,
+    /// The words' entries, serialized
+    pub data_size: u64,
+    /// The words' entries once compressed. With the interleaved layout,
+    /// they share frames with the Level 2 groups, which are counted too.
+    pub compressed_data_size: u64,
+    /// The whole compressed stream, forms included
+    pub stream_size: u64,
+    /// Key bytes the words' Level 2 entries share with the previous key,
+    /// so don't store
+    pub prefix_savings: u64,
+    pub entry_sizes: EntrySizeBuckets,
+    pub hot_words: u32,
+    pub strings: u32,
+}
+
+/// Words by the size of their serialized entry, which takes 1, 2, 3 or
+/// more bytes in Level 2 (LEB128)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "indexer", derive(Serialize))]
+pub struct EntrySizeBuckets {
+    pub under_128b: u32,
+    pub under_16kib: u32,
+    pub under_2mib: u32,
+    pub larger: u32,
+}
+
+impl EntrySizeBuckets {
+    fn add(&mut self, size: usize) {
+        let bucket = match size {
+            0..0x80 => &mut self.under_128b,
+            0x80..0x4000 => &mut self.under_16kib,
+            0x4000..0x20_0000 => &mut self.under_2mib,
+            _ => &mut self.larger,
+        };
+        *bucket += 1;
+    }
+}
+
+/// The `SOURCE_DATE_EPOCH` environment variable, which reproducible builds
+/// use as their timestamp (see <https://reproducible-builds.org/specs/source-date-epoch/>).
+/// `None` if it's unset, an error if it isn't a number of seconds.
+pub fn source_date_epoch() -> Result<Option<u64>, WriterError> {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(value) => parse_source_date_epoch(&value).map(Some),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(WriterError::InvalidTimestamp(format!(
+            "Invalid SOURCE_DATE_EPOCH: {}",
+            e
+        ))),
+    }
+}
+
+fn parse_source_date_epoch(value: &str) -> Result<u64, WriterError> {
+    (value.trim().parse()).map_err(|_| {
+        WriterError::InvalidTimestamp(format!(
+            "Invalid SOURCE_DATE_EPOCH: {:?} isn't a timestamp",
+            value
+        ))
+    })
+}
+
+/// Layout and compression settings of a `.dict`, recorded in its sections
+#[derive(Debug, Clone)]
+pub struct WriterOptions {
+    /// `Grouping::Bounded` caps the size of the Level 2 groups, so a lookup
+    /// never decompresses more than that to find an entry, whatever the
+    /// script (a 3 byte prefix is a single CJK character)
+    pub grouping: Grouping,
+    pub codec: Codec,
+    /// Levels and frame sizes, for the codecs that have them
+    pub compression: Compression,
+    /// `Offsets::U32` is smaller, but fails for streams over 4 GiB
+    pub offsets: Offsets,
+    /// `StreamLayout::Interleaved` keeps each group's binary data next to
+    /// it, so a cold lookup decompresses one frame instead of two
+    pub layout: StreamLayout,
+    /// Every Nth entry of a Level 2 group stores its full key, so lookups
+    /// can binary search the group instead of decoding it all. 0 disables
+    /// restart points.
+    pub restart_interval: u32,
+    /// Also write an `MPHF` section, so exact lookups of words skip Level 1
+    /// and Level 2. Costs ~16 bytes + the key per word.
+    pub mphf: bool,
+    /// Also write an `FSTM` section with the words, for regex, fuzzy and
+    /// range queries
+    pub fst: bool,
+    /// Size of the bloom filter of the words and forms, 0 for none. At 10
+    /// bits per key, ~1% of the misses still search Level 2.
+    pub bloom_bits_per_key: u32,
+    /// Gloss lines repeated at least this many times go to a shared string
+    /// table, 0 to disable it
+    pub string_table_min_count: u32,
+    /// ed25519 seed to sign the file with, see `DictionaryReader::verify_signature`
+    pub signing_key: Option<[u8; 32]>,
+    /// Words (or inflected forms of them) by decreasing frequency
+    pub frequency_list: Vec<String>,
+    /// How many of the most frequent words go to the hot frame, which the
+    /// reader keeps decompressed. 0 disables it.
+    pub hot_words: usize,
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        WriterOptions {
+            grouping: Grouping::Prefix3,
+            codec: Codec::Zstd,
+            compression: Compression::default(),
+            offsets: Offsets::U32,
+            layout: StreamLayout::Split,
+            restart_interval: 0,
+            mphf: false,
+            fst: false,
+            bloom_bits_per_key: 0,
+            string_table_min_count: 0,
+            signing_key: None,
+            frequency_list: Vec::new(),
+            hot_words: 0,
+        }
+    }
+}
+
+/// Writes `.dict` files:
+///
+/// ```no_run
+/// # use tarkka::writer::{DictionaryWriter, build_tagged_index};
+/// # use tarkka::Codec;
+/// # fn main() -> Result<(), tarkka::writer::WriterError> {
+/// let words = build_tagged_index(vec![], vec![])?;
+/// let file = std::fs::File::create("user.dict")?;
+/// DictionaryWriter::new()
+///     .codec(Codec::Lz4)
+///     .timestamp(1_700_000_000)
+///     .write(file, &words, &[])?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DictionaryWriter {
+    pub(crate) opts: WriterOptions,
+    /// The error of an invalid `SOURCE_DATE_EPOCH`, returned by the writes
+    /// unless [`timestamp`](Self::timestamp) replaces it
+    pub(crate) timestamp_s: Result<u64, String>,
+}
+
+impl Default for DictionaryWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DictionaryWriter {
+    /// Default options, timestamped with `SOURCE_DATE_EPOCH` if it's set,
+    /// else now. If it's set but invalid, writing fails with
+    /// `WriterError::InvalidTimestamp` unless a [`timestamp`](Self::timestamp)
+    /// is given.
+    pub fn new() -> Self {
+        let timestamp_s = match source_date_epoch() {
+            Ok(Some(timestamp_s)) => Ok(timestamp_s),
+            Ok(None) => Ok(SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs())),
+            Err(e) => Err(e.to_string()),
+        };
+        DictionaryWriter {
+            opts: WriterOptions::default(),
+            timestamp_s,
+        }
+    }
+
+    /// Replaces all the options
+    pub fn options(mut self, opts: WriterOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// Creation time recorded in the header, in seconds since the epoch
+    pub fn timestamp(mut self, timestamp_s: u64) -> Self {
+        self.timestamp_s = Ok(timestamp_s);
+        self
+    }
+
+    pub(crate) fn timestamp_s(&self) -> Result<u64, WriterError> {
+        (self.timestamp_s.clone()).map_err(WriterError::InvalidTimestamp)
+    }
+
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.opts.codec = codec;
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.opts.compression = compression;
+        self
+    }
+
+    /// Caps the raw size of the Level 2 groups, so a lookup never
+    /// decompresses more than that to find an entry
+    pub fn max_group_size(mut self, max_group_size: u32) -> Self {
+        self.opts.grouping = Grouping::Bounded { max_group_size };
+        self
+    }
+
+    /// `Offsets::Varint` lifts the 4 GiB limit of the stream
+    pub fn offsets(mut self, offsets: Offsets) -> Self {
+        self.opts.offsets = offsets;
+        self
+    }
+
+    pub fn opts(&self) -> &WriterOptions {
+        &self.opts
+    }
+
+    /// Writes `sorted_words` (see [`build_tagged_index`]) and `forms` (see
+    /// [`build_forms_index`]) as a `.dict`. Returns its sizes.
+    pub fn write<'a, W: Write>(
+        &self,
+        w: W,
+        sorted_words: impl IntoIterator<Item = &'a WordWithTaggedEntries>,
+        forms: &[(String, FormEntry)],
+    ) -> Result<BuildReport, WriterError> {
+        write_tagged(w, sorted_words, forms, self.timestamp_s()?, &self.opts)
+    }
+
+    /// Writes `sorted_words` as several `.dict` files of roughly
+    /// `shard_words` words each in `dir`, plus a `<name>.manifest`
+    pub fn write_sharded(
+        &self,
+        dir: &Path,
+        name: &str,
+        sorted_words: &[WordWithTaggedEntries],
+        forms: &[(String, FormEntry)],
+        shard_words: usize,
+    ) -> Result<ShardManifest, WriterError> {
+        write_sharded(
+            dir,
+            name,
+            sorted_words,
+            forms,
+            self.timestamp_s()?,
+            shard_words,
+            &self.opts,
+        )
+    }
+}
+
+/// An inflected form of a headword, as listed in Kaikki's `forms`
+#[derive(Debug, Clone)]
+pub struct InflectedForm {
+    pub form: String,
+    pub lemma: String,
+    pub tags: Vec<String>,
+}
+
+/// Groups inflected forms by form, dropping those whose lemma is not a
+/// headword in `sorted_words`.
+pub fn build_forms_index(
+    forms: Vec<InflectedForm>,
+    sorted_words: &[WordWithTaggedEntries],
+) -> Vec<(String, FormEntry)> {
+    let mut by_form: BTreeMap<String, Vec<FormOf>> = BTreeMap::new();
+    for f in forms {
+        let lemma = nfc_key(&f.lemma).into_owned();
+        let lemma_exists = sorted_words
+            .binary_search_by(|w| w.word.as_str().cmp(&lemma))
+            .is_ok();
+        if !lemma_exists {
+            continue;
+        }
+        let lemmas = by_form.entry(nfc_key(&f.form).into_owned()).or_default();
+        let tags = f.tags;
+        push_form_of(lemmas, FormOf { lemma, tags });
+    }
+    by_form
+        .into_iter()
+        .map(|(form, lemmas)| (form, FormEntry { lemmas }))
+        .collect()
+}
+
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.bytes().zip(b.bytes()).take_while(|(x, y)| x == y).count()
+}
+
+/// Level 1 table, Level 2 groups and binary data for a set of keys
+pub(crate) struct EncodedIndex<B = Vec<u8>> {
+    level1_data: Vec<u8>,
+    level2_data: Vec<u8>,
+    binary_data: B,
+    binary_size: u64,
+    count: u32,
+    /// Keys and the start of their binary data, by ordinal. Only kept when
+    /// building an MPHF, an FST or a bloom filter.
+    keys: Vec<(String, u64)>,
+    /// Ordinal of each Level 1 group's first entry
+    group_ordinals: Vec<u32>,
+    /// End of each group's Level 2 entries and binary data
+    group_ends: Vec<(usize, usize)>,
+    /// Keys and data of the entries in the hot frame
+    hot: Vec<(String, Vec<u8>)>,
+    /// Identifies the key order, see `SECTION_ORDINALS`
+    build_id: u64,
+    /// Key bytes shared with the previous entry, see [`BuildReport`]
+    prefix_savings: u64,
+    entry_sizes: EntrySizeBuckets,
+}
+
+/// Level 1 entry being built: key + Level 2 size + binary data start
+struct OpenGroup {
+    key: Vec<u8>,
+    l2_raw_size: u64,
+    binary_start: u64,
+    first_ordinal: u32,
+    entries: u32,
+    /// Level 2 offset and binary offset, relative to the group, of each
+    /// restart point
+    restarts: Vec<(u32, u32)>,
+}
+
+impl OpenGroup {
+    /// Whether the next entry must store its full key
+    fn restart_due(&self, interval: u32) -> bool {
+        interval > 0 && self.entries.is_multiple_of(interval)
+    }
+
+    /// Size of the restarts trailer if the group was closed now
+    fn trailer_size(&self, interval: u32) -> u64 {
+        match interval {
+            0 => 0,
+            _ => self.restarts.len() as u64 * 8 + 4,
+        }
+    }
+}
+
+impl<B> EncodedIndex<B> {
+    pub(crate) fn map_binary_data<C>(self, f: impl FnOnce(B) -> C) -> EncodedIndex<C> {
+        EncodedIndex {
+            level1_data: self.level1_data,
+            level2_data: self.level2_data,
+            binary_data: f(self.binary_data),
+            binary_size: self.binary_size,
+            count: self.count,
+            keys: self.keys,
+            group_ordinals: self.group_ordinals,
+            group_ends: self.group_ends,
+            hot: self.hot,
+            build_id: self.build_id,
+            prefix_savings: self.prefix_savings,
+            entry_sizes: self.entry_sizes,
+        }
+    }
+}
+
+/// Orders keys as the index stores them: groups must be contiguous, so by
+/// 3 byte key for Prefix3, then by bytes
+pub(crate) fn sort_key(grouping: Grouping, key: &str) -> Vec<u8> {
+    match grouping {
+        Grouping::Prefix3 if !key.is_empty() => {
+            let mut sort_key = level1_key(key.as_bytes()).to_vec();
+            sort_key.extend(key.as_bytes());
+            sort_key
+        }
+        _ => key.as_bytes().to_vec(),
+    }
+}
+
+/// `hot_keys` (NFC) go to the hot frame instead of the binary data
+fn encode_index<'a, T: CompactSerialize + 'a>(
+    entries: impl IntoIterator<Item = (&'a str, &'a T)>,
+    opts: &WriterOptions,
+    hot_keys: &HashSet<String>,
+) -> Result<EncodedIndex, WriterError> {
+    let mut entries: Vec<(Cow<str>, &T)> = entries
+        .into_iter()
+        .map(|(key, value)| (nfc_key(key), value))
+        .collect();
+    entries.sort_by_cached_key(|(key, _)| sort_key(opts.grouping, key));
+
+    let mut encoder = IndexEncoder::new(opts, Vec::with_capacity(32 * 1024 * 1024));
+    for (key, value) in &entries {
+        encoder.push(key, *value, hot_keys.contains(key.as_ref()))?;
+    }
+    encoder.finish()
+}
+
+/// Builds an index from entries pushed in index order (see `sort_key`),
+/// writing their binary data to `B` as it goes
+pub(crate) struct IndexEncoder<'o, B: Write> {
+    opts: &'o WriterOptions,
+    index: EncodedIndex<B>,
+    group: Option<OpenGroup>,
+    prev_word: String,
+    key_hasher: Sha256,
+}
+
+impl<'o, B: Write> IndexEncoder<'o, B> {
+    pub(crate) fn new(opts: &'o WriterOptions, binary_data: B) -> Self {
+        IndexEncoder {
+            opts,
+            index: EncodedIndex {
+                level1_data: Vec::with_capacity(64 * 4096),
+                level2_data: Vec::with_capacity(8 * 1024 * 1024),
+                binary_data,
+                binary_size: 0,
+                count: 0,
+                keys: Vec::new(),
+                group_ordinals: Vec::new(),
+                group_ends: Vec::new(),
+                hot: Vec::new(),
+                build_id: 0,
+                prefix_savings: 0,
+                entry_sizes: EntrySizeBuckets::default(),
+            },
+            group: None,
+            prev_word: String::new(),
+            key_hasher: Sha256::new(),
+        }
+    }
+
+    /// Appends `current_word` (NFC), which must sort after the previous
+    /// one. A `hot` entry goes to the hot frame.
+    pub(crate) fn push<T: CompactSerialize + ?Sized>(
+        &mut self,
+        current_word: &str,
+        value: &T,
+        hot: bool,
+    ) -> Result<(), WriterError> {
+        let opts = self.opts;
+        let (grouping, restart_interval) = (opts.grouping, opts.restart_interval);
+        if current_word.is_empty() {
+            return Err(WriterError::InvalidKey("empty key".to_string()));
+        }
+        let index = &mut self.index;
+        let ordinal = index.count;
+        index.count += 1;
+        self.key_hasher
+            .update((current_word.len() as u32).to_le_bytes());
+        self.key_hasher.update(current_word);
+
+        // {"word": "こんにちは", "lang": "Japanese", "lang_code": "ja", "redirects": ["今日は"], "pos": "soft-redirect", "senses": [{"tags": ["no-gloss"]}]}
+        let serialize_error = |error| WriterError::Entry {
+            key: current_word.to_string(),
+            error,
+        };
+        // Hot entries keep a 0 size entry in Level 2, which no serialized
+        // value has
+        let ser_size = match hot {
+            true => {
+                let mut data = Vec::new();
+                value.serialize(&mut data).map_err(serialize_error)?;
+                index.entry_sizes.add(data.len());
+                index.hot.push((current_word.to_string(), data));
+                0
+            }
+            false => {
+                let size = value
+                    .serialize(&mut index.binary_data)
+                    .map_err(serialize_error)?;
+                index.entry_sizes.add(size);
+                size
+            }
+        };
+        let ss = VarU64(ser_size as u64);
+
+        let prev_word = self.prev_word.as_str();
+        let mut shared_len = common_prefix_len(prev_word, current_word);
+        let starts_group = match (&self.group, grouping) {
+            (None, _) => true,
+            (Some(g), Grouping::Prefix3) => g.key != level1_key(current_word.as_bytes()),
+            (Some(g), Grouping::Bounded { max_group_size }) => {
+                // A restart point stores the full key, plus 8 B in the trailer
+                let (shared_len, restart_size) = match g.restart_due(restart_interval) {
+                    true => (0, 8),
+                    false => (shared_len, 0),
+                };
+                let entry_size =
+                    2 + current_word.len() - shared_len + ss.serialized_len() + restart_size;
+                g.l2_raw_size + g.trailer_size(restart_interval) + entry_size as u64
+                    > max_group_size as u64
+                    || is_cut_point(
+                        current_word.as_bytes(),
+                        g.l2_raw_size,
+                        max_group_size as u64 * 3 / 4,
+                        16,
+                    )
+            }
+        };
+        if starts_group {
+            if let Some(g) = self.group.take() {
+                close_group(index, opts, g)?;
+            }
+            let key = match grouping {
+                Grouping::Prefix3 => level1_key(current_word.as_bytes()).to_vec(),
+                Grouping::Bounded { .. } => separator_key(prev_word, current_word).into(),
+            };
+            self.group = Some(OpenGroup {
+                key,
+                l2_raw_size: 0,
+                binary_start: index.binary_size,
+                first_ordinal: ordinal,
+                entries: 0,
+                restarts: Vec::new(),
+            });
+            // Each group is decoded on its own, so it can't share a prefix
+            // with the previous one
+            shared_len = 0;
+        }
+        let g = self.group.as_mut().expect("a group is open");
+        if g.restart_due(restart_interval) {
+            shared_len = 0;
+            let l2_offset = u32::try_from(g.l2_raw_size);
+            let binary_offset = u32::try_from(index.binary_size - g.binary_start);
+            match (l2_offset, binary_offset) {
+                (Ok(l2), Ok(bin)) => g.restarts.push((l2, bin)),
+                _ => {
+                    return Err(WriterError::TooLarge(format!(
+                        "Level 2 group of {}",
+                        current_word
+                    )));
+                }
+            }
+        }
+        g.entries += 1;
+
+        let suffix = &current_word.as_bytes()[shared_len..];
+        if shared_len > 127 || suffix.len() > 255 {
+            return Err(WriterError::InvalidKey(format!(
+                "{} is too long",
+                current_word
+            )));
+        }
+        if suffix.is_empty() {
+            return Err(WriterError::InvalidKey(format!(
+                "{} is duplicated",
+                current_word
+            )));
+        }
+
+        index.prefix_savings += shared_len as u64;
+        index.level2_data.push(shared_len as u8);
+        index.level2_data.push(suffix.len() as u8);
+        index.level2_data.extend_from_slice(suffix);
+        let vlen = ss.serialize(&mut index.level2_data)?;
+
+        let fixed_ovh = 2 + vlen;
+        let entry_size = suffix.len() + fixed_ovh;
+        if opts.mphf || opts.fst || opts.bloom_bits_per_key > 0 {
+            index
+                .keys
+                .push((current_word.to_string(), index.binary_size));
+        }
+        index.binary_size += ser_size as u64;
+        g.l2_raw_size += entry_size as u64;
+
+        self.prev_word.clear();
+        self.prev_word.push_str(current_word);
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> Result<EncodedIndex<B>, WriterError> {
+        if let Some(g) = self.group.take() {
+            close_group(&mut self.index, self.opts, g)?;
+        }
+        self.index.binary_data.flush()?;
+        let digest = self.key_hasher.finalize();
+        self.index.build_id = u64::from_le_bytes(digest[..8].try_into().unwrap());
+        Ok(self.index)
+    }
+}
+
+/// Whether a group (or region) of `size` bytes should end before `next`, on
+/// top of when it's full: once it's past `min_size`, `next`'s hash picks 1
+/// in `one_in` of them. Inserting or removing a word then only moves the
+/// boundaries next to it instead of every one after it, so the rest of the
+/// file keeps its bytes and delta patches (see [`crate::patch`]) stay
+/// small.
+fn is_cut_point(next: &[u8], size: u64, min_size: u64, one_in: u64) -> bool {
+    size >= min_size && mphf::hash64(next, CUT_POINT_SEED).is_multiple_of(one_in)
+}
+
+const CUT_POINT_SEED: u64 = 0xc07;
+
+/// Appends the group's restarts trailer, if any, to Level 2 and its entry
+/// to Level 1. The group's binary data ends at the index's.
+fn close_group<B>(
+    index: &mut EncodedIndex<B>,
+    opts: &WriterOptions,
+    mut group: OpenGroup,
+) -> Result<(), WriterError> {
+    // restarts: (l2 offset u32, binary offset u32) * count | count u32
+    if opts.restart_interval > 0 {
+        group.l2_raw_size += group.trailer_size(opts.restart_interval);
+        for (l2_offset, binary_offset) in &group.restarts {
+            index.level2_data.extend(l2_offset.to_le_bytes());
+            index.level2_data.extend(binary_offset.to_le_bytes());
+        }
+        index
+            .level2_data
+            .extend((group.restarts.len() as u32).to_le_bytes());
+    }
+    index.group_ordinals.push(group.first_ordinal);
+    (index.group_ends).push((index.level2_data.len(), index.binary_size as usize));
+    push_level1_entry(&mut index.level1_data, opts.grouping, opts.offsets, group)
+}
+
+// L1 size ~ 71KB (stays in memory, need to read entirely)
+// L2 size 5~10MB (seek, useful to not store entire word ever)
+fn push_level1_entry(
+    level1_data: &mut Vec<u8>,
+    grouping: Grouping,
+    offsets: Offsets,
+    group: OpenGroup,
+) -> Result<(), WriterError> {
+    // Prefix3: key (3 B) | l2 size | binary start
+    // Bounded: key len (1 B) | key | l2 size | binary start
+    match grouping {
+        Grouping::Prefix3 => assert_eq!(group.key.len(), 3),
+        Grouping::Bounded { .. } => {
+            let len = u8::try_from(group.key.len()).map_err(|_| {
+                WriterError::InvalidKey(format!(
+                    "{} is too long",
+                    String::from_utf8_lossy(&group.key)
+                ))
+            })?;
+            level1_data.push(len);
+        }
+    }
+    level1_data.extend(&group.key);
+    offsets
+        .write(level1_data, group.l2_raw_size)
+        .map_err(WriterError::TooLarge)?;
+    offsets
+        .write(level1_data, group.binary_start)
+        .map_err(WriterError::TooLarge)?;
+    Ok(())
+}
+
+/// The index's groups, each with its Level 2 entries followed by its binary
+/// data, packed into regions of up to `frame_size` bytes (compressed sizes
+/// are used as uncompressed ones) without splitting groups, and passed to
+/// `emit` in order. A group larger than that gets a region of its own.
+/// Past half of it, regions also end at groups picked by [`is_cut_point`].
+fn interleave<B: Read>(
+    index: &mut EncodedIndex<B>,
+    frame_size: FrameSize,
+    mut emit: impl FnMut(&[u8]) -> io::Result<()>,
+) -> io::Result<()> {
+    let max_region_size = match frame_size {
+        FrameSize::Uncompressed(size) | FrameSize::Compressed(size) => size as usize,
+    };
+    let mut region = Vec::new();
+    let (mut level2_start, mut binary_start) = (0, 0);
+    for &(level2_end, binary_end) in &index.group_ends {
+        let group_size = (level2_end - level2_start) + (binary_end - binary_start);
+        let group = &index.level2_data[level2_start..level2_end];
+        if !region.is_empty()
+            && (region.len() + group_size > max_region_size
+                || is_cut_point(group, region.len() as u64, max_region_size as u64 / 2, 2))
+        {
+            emit(&region)?;
+            region.clear();
+        }
+        region.extend(&index.level2_data[level2_start..level2_end]);
+        let data_start = region.len();
+        region.resize(data_start + binary_end - binary_start, 0);
+        index.binary_data.read_exact(&mut region[data_start..])?;
+        (level2_start, binary_start) = (level2_end, binary_end);
+    }
+    if !region.is_empty() {
+        emit(&region)?;
+    }
+    Ok(())
+}
+
+/// The `count` most frequent headwords in `frequency_list` (NFC), counting
+/// an inflected form as a hit on its lemmas
+fn hot_keys(
+    words: &[&WordWithTaggedEntries],
+    forms: &[(String, FormEntry)],
+    frequency_list: &[String],
+    count: usize,
+) -> HashSet<String> {
+    if count == 0 {
+        return HashSet::new();
+    }
+    let headwords: HashSet<Cow<str>> = words.iter().map(|w| nfc_key(&w.word)).collect();
+    let forms: HashMap<Cow<str>, &FormEntry> = (forms.iter())
+        .map(|(form, entry)| (nfc_key(form), entry))
+        .collect();
+    let form_lemmas = |form: &str| match forms.get(form) {
+        Some(entry) => (entry.lemmas.iter())
+            .map(|l| nfc_key(&l.lemma).into_owned())
+            .collect(),
+        None => vec![],
+    };
+    rank_hot_keys(
+        frequency_list,
+        count,
+        |word| headwords.contains(word),
+        form_lemmas,
+    )
+}
+
+/// [`hot_keys`], given which keys are headwords and the lemmas (NFC) of
+/// each inflected form
+pub(crate) fn rank_hot_keys(
+    frequency_list: &[String],
+    count: usize,
+    is_headword: impl Fn(&str) -> bool,
+    form_lemmas: impl Fn(&str) -> Vec<String>,
+) -> HashSet<String> {
+    let mut hot = HashSet::new();
+    if count == 0 {
+        return hot;
+    }
+    for word in frequency_list {
+        let word = nfc_key(word);
+        let lemmas = match is_headword(&word) {
+            true => vec![word.into_owned()],
+            false => (form_lemmas(&word).into_iter())
+                .filter(|l| is_headword(l))
+                .collect(),
+        };
+        for lemma in lemmas {
+            if hot.len() == count {
+                return hot;
+            }
+            hot.insert(lemma);
+        }
+    }
+    hot
+}
+
+/// A word, serialized with its gloss lines in `strings` as references
+pub(crate) struct Interned<'a> {
+    pub(crate) word: &'a WordWithTaggedEntries,
+    pub(crate) strings: &'a StringTable,
+}
+
+impl CompactSerialize for Interned<'_> {
+    fn serialize<W: Write>(&self, out: &mut W) -> Result<usize, SerializeError> {
+        match self.strings.is_empty() {
+            true => self.word.serialize(out),
+            false => self.strings.intern(self.word).serialize(out),
+        }
+    }
+}
+
+fn write_tagged<'a, W: Write>(
+    w: W,
+    sorted_words: impl IntoIterator<Item = &'a WordWithTaggedEntries>,
+    forms: &[(String, FormEntry)],
+    timestamp_s: u64,
+    opts: &WriterOptions,
+) -> Result<BuildReport, WriterError> {
+    let sorted_words: Vec<&WordWithTaggedEntries> = sorted_words.into_iter().collect();
+    let strings = match opts.string_table_min_count {
+        0 => StringTable::default(),
+        min_count => StringTable::build(sorted_words.iter().copied(), min_count),
+    };
+    let interned: Vec<Interned> = (sorted_words.iter())
+        .map(|word| Interned {
+            word,
+            strings: &strings,
+        })
+        .collect();
+    let hot_keys = hot_keys(&sorted_words, forms, &opts.frequency_list, opts.hot_words);
+    let words = encode_index(
+        interned.iter().map(|i| (i.word.word.as_str(), i)),
+        opts,
+        &hot_keys,
+    )?;
+    let forms = encode_index(
+        forms.iter().map(|(form, entry)| (form.as_str(), entry)),
+        opts,
+        &HashSet::new(),
+    )?;
+    write_dict(
+        w,
+        words.map_binary_data(Cursor::new),
+        forms.map_binary_data(Cursor::new),
+        &strings,
+        timestamp_s,
+        opts,
+        Cursor::new(Vec::new()),
+    )
+}
+
+/// Compresses the indexes into `stream`, a scratch buffer, then writes the
+/// whole `.dict` to `w`
+pub(crate) fn write_dict<W: Write, B: Read, F: Read, S: Read + Write + Seek>(
+    mut w: W,
+    mut words: EncodedIndex<B>,
+    mut forms: EncodedIndex<F>,
+    strings: &StringTable,
+    timestamp_s: u64,
+    opts: &WriterOptions,
+    mut stream: S,
+) -> Result<BuildReport, WriterError> {
+    // Decompressed stream: [words L2 | words data | forms L2 | forms data],
+    // or each index's groups interleaved with their data
+    let compression = opts.compression;
+    let mut encoder = StreamEncoder::new(opts.codec, &mut stream);
+    let mut compressed_data_size = 0;
+    match opts.layout {
+        StreamLayout::Split => {
+            encoder.region(words.level2_data.as_slice(), compression.level2)?;
+            compressed_data_size = encoder.region(&mut words.binary_data, compression.data)?;
+            encoder.region(forms.level2_data.as_slice(), compression.level2)?;
+            encoder.region(&mut forms.binary_data, compression.data)?;
+        }
+        StreamLayout::Interleaved => {
+            // One frame per region
+            let mut emit = |region: &[u8]| encoder.frame(region, compression.data.level);
+            interleave(&mut words, compression.data.frame_size, |region| {
+                compressed_data_size += emit(region)?;
+                Ok(())
+            })?;
+            interleave(&mut forms, compression.data.frame_size, |region| {
+                emit(region).map(drop)
+            })?;
+        }
+    }
+    encoder.finish()?;
+    let stream_size = stream.stream_position()?;
+
+    let level2_size = words.level2_data.len() as u64;
+    let total_ser_size = words.binary_size;
+    // With varint offsets, the real size is in the offsets section
+    let header_level2_size = match opts.offsets {
+        Offsets::U32 => u32::try_from(level2_size).map_err(|_| {
+            WriterError::TooLarge(format!("Level 2 size {} for 32-bit offsets", level2_size))
+        })?,
+        Offsets::Varint => u32::try_from(level2_size).unwrap_or(u32::MAX),
+    };
+
+    let mut sections = Vec::new();
+    write_section(
+        &mut sections,
+        SECTION_NORMALIZATION,
+        &[Normalization::Nfc as u8],
+    )?;
+    write_section(&mut sections, SECTION_GROUPING, &opts.grouping.to_bytes())?;
+    write_section(&mut sections, SECTION_COMPRESSION, &compression.to_bytes())?;
+    write_section(&mut sections, SECTION_LAYOUT, &[opts.layout as u8])?;
+    let mut offsets_payload = vec![opts.offsets as u8];
+    offsets_payload.extend(level2_size.to_le_bytes());
+    write_section(&mut sections, SECTION_OFFSETS, &offsets_payload)?;
+    write_section(
+        &mut sections,
+        SECTION_RESTARTS,
+        &opts.restart_interval.to_le_bytes(),
+    )?;
+    let mut ordinals_payload = Vec::with_capacity(8 + words.group_ordinals.len() * 4);
+    ordinals_payload.extend(words.build_id.to_le_bytes());
+    for ordinal in &words.group_ordinals {
+        ordinals_payload.extend(ordinal.to_le_bytes());
+    }
+    write_section(&mut sections, SECTION_ORDINALS, &ordinals_payload)?;
+    if opts.mphf {
+        let entries: Vec<(&str, u64)> = words.keys.iter().map(|(k, o)| (k.as_str(), *o)).collect();
+        let mphf = MphfIndex::build(&entries, total_ser_size).map_err(WriterError::Index)?;
+        write_section(&mut sections, SECTION_MPHF, &mphf.to_bytes())?;
+    }
+    if opts.fst {
+        // Ordinals follow the index order, which isn't byte order with
+        // Prefix3 grouping
+        let mut keys: Vec<(&[u8], u64)> = (words.keys.iter().enumerate())
+            .map(|(ordinal, (k, _))| (k.as_bytes(), ordinal as u64))
+            .collect();
+        keys.sort_unstable();
+        let map = fst::Map::from_iter(keys).map_err(|e| WriterError::Index(e.to_string()))?;
+        write_section(&mut sections, SECTION_FST, map.as_fst().as_bytes())?;
+    }
+    if opts.bloom_bits_per_key > 0 {
+        let keys: Vec<&str> = (words.keys.iter().chain(&forms.keys))
+            .map(|(k, _)| k.as_str())
+            .collect();
+        let bloom = BloomFilter::build(&keys, opts.bloom_bits_per_key);
+        write_section(&mut sections, SECTION_BLOOM, &bloom.to_bytes())?;
+    }
+    if forms.count > 0 {
+        let forms_l2_off = level2_size + total_ser_size;
+        let forms_data_off = forms_l2_off + forms.level2_data.len() as u64;
+        let mut payload = Vec::with_capacity(12 + forms.level1_data.len());
+        for offset in [forms_l2_off, forms.level2_data.len() as u64, forms_data_off] {
+            (opts.offsets)
+                .write(&mut payload, offset)
+                .map_err(WriterError::TooLarge)?;
+        }
+        payload.extend(&forms.level1_data);
+        write_section(&mut sections, SECTION_FORMS, &payload)?;
+    }
+
+    if !words.hot.is_empty() {
+        // count | (key | size | data) per entry, compressed as a single frame
+        let mut hot = Vec::new();
+        VarU64(words.hot.len() as u64).serialize(&mut hot)?;
+        for (key, data) in &words.hot {
+            CompactSerialize::serialize(key, &mut hot)?;
+            VarU64(data.len() as u64).serialize(&mut hot)?;
+            hot.extend(data);
+        }
+        let mut payload = (hot.len() as u64).to_le_bytes().to_vec();
+        payload.extend(compress_frame(opts.codec, &hot, compression.data.level));
+        write_section(&mut sections, SECTION_HOT_WORDS, &payload)?;
+    }
+    if !strings.is_empty() {
+        write_section(&mut sections, SECTION_STRINGS, &strings.to_bytes()?)?;
+    }
+    // Signed last, so the signature ends the section directory
+    if opts.signing_key.is_some() {
+        write_section(&mut sections, SECTION_SIGNATURE, &[0; SIGNATURE_LEN])?;
+    }
+
+    let level1_data = words.level1_data;
+    let word_count = words.count;
+    let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+    header.extend(b"DICT");
+    header.extend((level1_data.len() as u32).to_le_bytes());
+    header.extend(header_level2_size.to_le_bytes());
+    header.extend(word_count.to_le_bytes());
+    // ^16
+    header.extend(timestamp_s.to_le_bytes());
+    // ^24
+    header.push(TARKKA_FMT_VERSION);
+    // ^25
+    header.push(opts.codec as u8);
+    // reserved 2 bytes
+    header.extend([0, 0]); // 28
+    header.extend((sections.len() as u32).to_le_bytes()); // 32
+
+    if let Some(key) = &opts.signing_key {
+        let sig_start = sections.len() - SIGNATURE_LEN;
+        let sig_offset = (header.len() + level1_data.len() + sig_start) as u64;
+        stream.seek(SeekFrom::Start(0))?;
+        let file = (header.as_slice())
+            .chain(level1_data.as_slice())
+            .chain(sections.as_slice())
+            .chain(&mut stream);
+        let digest = signature::file_digest(file, sig_offset)?;
+        sections[sig_start..].copy_from_slice(&signature::sign(key, &digest));
+    }
+
+    w.write_all(&header)?;
+    w.write_all(&level1_data)?;
+    w.write_all(&sections)?;
+    stream.seek(SeekFrom::Start(0))?;
+    io::copy(&mut stream, &mut w)?;
+    w.flush()?;
+
+    Ok(BuildReport {
+        word_count,
+        form_count: forms.count,
+        file_size: (header.len() + level1_data.len() + sections.len()) as u64 + stream_size,
+        level1_size: level1_data.len() as u64,
+        sections_size: sections.len() as u64,
+        level2_size,
+        data_size: total_ser_size,
+        compressed_data_size,
+        stream_size,
+        prefix_savings: words.prefix_savings,
+        entry_sizes: words.entry_sizes,
+        hot_words: words.hot.len() as u32,
+        strings: strings.len() as u32,
+    })
+}
+
+/// Writes `sorted_words` as several `.dict` files of `shard_words` words
+/// each (the last one may have fewer), plus a `<name>.manifest` describing
+/// which key range lives in which shard. Each form is stored in the shard
+/// covering the form itself, as that's the shard a lookup for it hits.
+fn write_sharded(
+    dir: &Path,
+    name: &str,
+    sorted_words: &[WordWithTaggedEntries],
+    forms: &[(String, FormEntry)],
+    timestamp_s: u64,
+    shard_words: usize,
+    opts: &WriterOptions,
+) -> Result<ShardManifest, WriterError> {
+    std::fs::create_dir_all(dir)?;
+
+    let shards: Vec<&[WordWithTaggedEntries]> = sorted_words.chunks(shard_words.max(1)).collect();
+    // What the manifest can hold, checked before writing any shard
+    if shards.len() > u16::MAX as usize {
+        return Err(WriterError::TooLarge(format!(
+            "{} shards of {} words, the manifest holds up to 65535",
+            shards.len(),
+            shard_words
+        )));
+    }
+    let mut manifest = ShardManifest {
+        timestamp_s,
+        word_count: 0,
+        shards: Vec::with_capacity(shards.len()),
+    };
+    for (i, words) in shards.iter().enumerate() {
+        // The first shard also covers every key before its first word
+        let first_key = match i {
+            0 => "",
+            _ => separator_key(&shards[i - 1][shards[i - 1].len() - 1].word, &words[0].word),
+        };
+        if first_key.len() > u8::MAX as usize {
+            return Err(WriterError::InvalidKey(format!(
+                "{} is too long",
+                first_key
+            )));
+        }
+        let filename = format!("{}.{}.dict", name, i);
+        if filename.len() > u8::MAX as usize {
+            return Err(WriterError::TooLarge(format!(
+                "shard filename {} is over 255 bytes",
+                filename
+            )));
+        }
+        manifest.shards.push(ShardInfo {
+            first_key: first_key.to_string(),
+            word_count: words.len() as u32,
+            filename,
+        });
+    }
+
+    // Each form goes to the shard covering it
+    let mut shard_forms: Vec<Vec<(String, FormEntry)>> = vec![Vec::new(); shards.len()];
+    for (form, entry) in forms {
+        if let Some(i) = manifest.shard_for(form) {
+            shard_forms[i].push((form.clone(), entry.clone()));
+        }
+    }
+
+    for ((i, words), shard_forms) in shards.into_iter().enumerate().zip(shard_forms) {
+        let file = File::create(dir.join(&manifest.shards[i].filename))?;
+        let word_count = write_tagged(file, words, &shard_forms, timestamp_s, opts)?.word_count;
+        manifest.word_count += word_count;
+    }
+
+    let manifest_path = dir.join(format!("{}.manifest", name));
+    manifest.write(File::create(&manifest_path)?)?;
+    Ok(manifest)
+}
+
+// multiple entries with a single sense into 1 entry with multiple senses
+pub fn aggregate_entries(
+    entries: Vec<WordWithTaggedEntries>,
+) -> Result<WordEntryComplete, WriterError> {
+    let no_entries = |word: &str| WriterError::NoEntries(word.to_string());
+    let first = entries.first().ok_or_else(|| no_entries(""))?;
+    let mut base = first
+        .entries
+        .first()
+        .ok_or_else(|| no_entries(&first.word))?
+        .clone();
+
+    if entries.len() == 1 {
+        // Still need to compress categories and merge senses even for single entry
+        merge_same_pos_senses(&mut base.senses);
+        return Ok(base);
+    }
+
+    // Take the first entry as base and aggregate others into it
+
+    for entry_data in entries.into_iter() {
+        for word_entry in entry_data.entries {
+            // Aggregate senses with POS preserved in each sense
+            for sense in word_entry.senses {
+                base.senses.push(sense);
+            }
+        }
+    }
+
+    // Merge senses with the same POS and compress categories after aggregation
+    merge_same_pos_senses(&mut base.senses);
+    Ok(base)
+}
+
+/// Merges the senses with the same POS into one, and sorts them by POS
+/// name and their links by kind and target, dropping repeats. Glosses keep
+/// the order they first come in, the input's: Kaikki lists a word's
+/// etymologies and senses in Wiktionary's order.
+pub(crate) fn merge_same_pos_senses(senses: &mut Vec<Sense>) {
+    let mut merged_senses: Vec<Sense> = Vec::new();
+
+    for sense in senses.drain(..) {
+        // remove SoftRedirects here, they are already part of redirects
+        if sense.pos == PartOfSpeech::SoftRedirect {
+            continue;
+        }
+        match merged_senses.iter_mut().find(|s| s.pos == sense.pos) {
+            Some(existing_sense) => {
+                existing_sense.glosses.extend(sense.glosses);
+                existing_sense.links.extend(sense.links);
+            }
+            None => merged_senses.push(sense),
+        }
+    }
+
+    merged_senses.retain(|e| !e.glosses.iter().all(|g| g.gloss_lines.is_empty()));
+    for sense in &mut merged_senses {
+        let mut seen = HashSet::new();
+        sense.glosses.retain(|g| seen.insert(g.clone()));
+        sense.links.sort_unstable();
+        sense.links.dedup();
+    }
+    // Each POS is there once
+    merged_senses.sort_by_cached_key(|s| s.pos.to_string());
+    *senses = merged_senses;
+}
+
+/// Merges the entries of each (NFC) word into a single word, tagged by the
+/// kinds of entries it has, sorted by word
+pub fn build_tagged_index(
+    monolingual_entries: Vec<WordWithTaggedEntries>,
+    english_entries: Vec<WordWithTaggedEntries>,
+) -> Result<Vec<WordWithTaggedEntries>, WriterError> {
+    let mut word_groups: HashMap<String, (Vec<WordWithTaggedEntries>, Vec<WordWithTaggedEntries>)> =
+        HashMap::new();
+
+    // Populate monolingual entries
+    for entry in monolingual_entries {
+        let word_str = nfc_key(&entry.word).into_owned();
+        word_groups
+            .entry(word_str)
+            .or_insert((Vec::new(), Vec::new()))
+            .0
+            .push(entry);
+    }
+
+    // Populate English entries
+    for entry in english_entries {
+        let word_str = nfc_key(&entry.word).into_owned();
+        word_groups
+            .entry(word_str)
+            .or_insert((Vec::new(), Vec::new()))
+            .1
+            .push(entry);
+    }
+
+    // Get all words in alphabetical order
+    let mut words: Vec<String> = word_groups.keys().cloned().collect();
+    words.sort();
+
+    let mut result: Vec<WordWithTaggedEntries> = Vec::new();
+
+    for word in words {
+        let (mono_entries, eng_entries) = word_groups.remove(&word).unwrap();
+        result.push(merge_word(word, mono_entries, eng_entries)?);
+    }
+    Ok(result)
+}
+
+/// A single word out of all its monolingual and English entries, see
+/// [`build_tagged_index`]
+pub(crate) fn merge_word(
+    word: String,
+    mono_entries: Vec<WordWithTaggedEntries>,
+    eng_entries: Vec<WordWithTaggedEntries>,
+) -> Result<WordWithTaggedEntries, WriterError> {
+    // Helper to extract sounds and hyphenations from entries
+    let extract_sound_and_hyph =
+        |entries: &[WordWithTaggedEntries]| -> (Option<String>, Vec<String>) {
+            let selected_sound = entries.iter().find_map(|e| e.sounds.clone());
+            let selected_hyphenation = entries
+                .iter()
+                .find(|e| !e.hyphenations.is_empty())
+                .map(|e| e.hyphenations.clone())
+                .unwrap_or_default();
+            (selected_sound, selected_hyphenation)
+        };
+
+    let tag = match (mono_entries.is_empty(), eng_entries.is_empty()) {
+        (false, true) => WordTag::Monolingual,
+        (true, false) => WordTag::English,
+        (false, false) => WordTag::Both,
+        (true, true) => unreachable!("Empty word group"),
+    };
+
+    let (entries, selected_sound, selected_hyphenation, redirects) = match tag {
+        WordTag::Monolingual => {
+            let (sound, hyph) = extract_sound_and_hyph(&mono_entries);
+            let redirects: Vec<String> = mono_entries
+                .iter()
+                .flat_map(|e| e.redirects.clone())
+                .collect();
+            let entry = aggregate_entries(mono_entries)?;
+
+            (vec![entry], sound, hyph, redirects)
+        }
+        WordTag::English => {
+            let redirects: Vec<String> = eng_entries
+                .iter()
+                .flat_map(|e| e.redirects.clone())
+                .collect();
+            let (sound, hyph) = extract_sound_and_hyph(&eng_entries);
+            let entry = aggregate_entries(eng_entries)?;
+            (vec![entry], sound, hyph, redirects)
+        }
+        WordTag::Both => {
+            let mut eng_redirects: Vec<_> = eng_entries
+                .iter()
+                .flat_map(|e| e.redirects.clone())
+                .collect();
+            let mut redirects: Vec<String> = mono_entries
+                .iter()
+                .flat_map(|e| e.redirects.clone())
+                .collect();
+
+            // Prefer monolingual sound/hyphenation, fallback to English
+            let (mono_sound, mono_hyph) = extract_sound_and_hyph(&mono_entries);
+            let (eng_sound, eng_hyph) = extract_sound_and_hyph(&eng_entries);
+
+            let eng_entry = aggregate_entries(eng_entries)?;
+            let mono_entry = aggregate_entries(mono_entries)?;
+
+            redirects.append(&mut eng_redirects);
+
+            let selected_sound = mono_sound.or(eng_sound);
+            let selected_hyphenation = if !mono_hyph.is_empty() {
+                mono_hyph
+            } else {
+                eng_hyph
+            };
+
+            (
+                vec![mono_entry, eng_entry],
+                selected_sound,
+                selected_hyphenation,
+                redirects,
+            )
+        }
+    };
+
+    Ok(WordWithTaggedEntries {
+        word,
+        id: None,
+        tag,
+        entries,
+        sounds: selected_sound,
+        hyphenations: selected_hyphenation,
+        redirects: redirects.iter().cloned().unique().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RegionCompression;
+    use crate::reader::DictionaryReader;
+    use crate::test_util::{mono_words, open_dict, plural_forms, tagged_words, write_dict};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_build_tagged_index() {
+        let result = tagged_words(&[
+            ("dictate", "verb", "to say words aloud", true),
+            ("dictionary", "noun", "a book of word definitions", true),
+            ("dictionary", "noun", "a reference book", false),
+            ("dictoto", "noun", "fictional word for testing", true),
+            ("pa", "noun", "short word", false),
+            ("papa", "noun", "father", true),
+            ("papo", "noun", "chat", true),
+            ("potato", "noun", "a vegetable", false),
+        ]);
+
+        assert_eq!(result.len(), 7);
+
+        let words: Vec<&str> = result.iter().map(|w| w.word.as_str()).collect();
+        assert_eq!(
+            words,
+            vec![
+                "dictate",
+                "dictionary",
+                "dictoto",
+                "pa",
+                "papa",
+                "papo",
+                "potato"
+            ]
+        );
+
+        // Check tags
+        let dictionary = result.iter().find(|w| w.word == "dictionary").unwrap();
+        assert!(matches!(dictionary.tag, WordTag::Both));
+        assert_eq!(dictionary.entries.len(), 2); // Exactly 2 entries for Both tag
+
+        let dictate = result.iter().find(|w| w.word == "dictate").unwrap();
+        assert!(matches!(dictate.tag, WordTag::Monolingual));
+        assert_eq!(dictate.entries.len(), 1);
+
+        let pa = result.iter().find(|w| w.word == "pa").unwrap();
+        assert!(matches!(pa.tag, WordTag::English));
+        assert_eq!(pa.entries.len(), 1);
+    }
+
+    #[test]
+    fn test_tagged_write_read_roundtrip() {
+        let tagged_words = tagged_words(&[
+            ("dictate", "verb", "to say words aloud", true),
+            ("dictionary", "noun", "a book of word definitions", true),
+            ("dictionary", "noun", "reference book", false),
+            ("dictoto", "noun", "fictional word for testing", true),
+            ("pa", "noun", "short word", false),
+            ("papa", "noun", "father", true),
+            ("papo", "noun", "chat", true),
+            ("potato", "noun", "a vegetable", false),
+        ]);
+
+        let opts = WriterOptions {
+            grouping: Grouping::Prefix3,
+            ..Default::default()
+        };
+        let mut dict_reader = open_dict(&tagged_words, &[], &opts);
+
+        let result = dict_reader.lookup("dictionary").unwrap();
+        assert!(result.is_some());
+        let word = result.unwrap();
+        assert_eq!(word.word, "dictionary");
+        assert!(matches!(word.tag, WordTag::Both));
+        assert_eq!(word.entries.len(), 2); // Exactly 2 entries for Both tag
+        assert_eq!(word.entries[0].senses[0].pos, PartOfSpeech::Noun); // First entry is monolingual
+        assert_eq!(
+            word.entries[0].senses[0].glosses[0].gloss_lines[0],
+            "a book of word definitions"
+        );
+        assert_eq!(word.entries[1].senses[0].pos, PartOfSpeech::Noun); // Second entry is English
+        assert_eq!(
+            word.entries[1].senses[0].glosses[0].gloss_lines[0],
+            "reference book"
+        );
+
+        let result = dict_reader.lookup("papa").unwrap();
+        assert!(result.is_some());
+        let word = result.unwrap();
+        assert_eq!(word.word, "papa");
+        assert!(matches!(word.tag, WordTag::Monolingual));
+        assert_eq!(word.entries.len(), 1);
+        assert_eq!(word.entries[0].senses[0].pos, PartOfSpeech::Noun);
+
+        let result = dict_reader.lookup("nonexistent").unwrap();
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_offsets() {
+        let words: Vec<String> = (0..300).map(|i| format!("perro{}", i)).collect();
+        let tagged_words = mono_words(&words);
+        let forms = plural_forms(&[("perros", "perro7")], &tagged_words);
+
+        let bounded = Grouping::Bounded {
+            max_group_size: 4096,
+        };
+        for grouping in [Grouping::Prefix3, bounded] {
+            let mut sizes = Vec::new();
+            for offsets in [Offsets::U32, Offsets::Varint] {
+                let opts = WriterOptions {
+                    grouping,
+                    offsets,
+                    restart_interval: 8,
+                    ..Default::default()
+                };
+                let buffer = write_dict(&tagged_words, &forms, &opts);
+                sizes.push(buffer.len());
+
+                let mut dict_reader = DictionaryReader::open(Cursor::new(buffer)).unwrap();
+                for w in &words {
+                    let found = dict_reader.lookup(w).unwrap().expect("word missing");
+                    assert_eq!(found.entries[0].senses[0].glosses[0].gloss_lines[0], *w);
+                }
+                assert_eq!(
+                    dict_reader.lookup("perros").unwrap().unwrap().word,
+                    "perro7"
+                );
+                assert!(dict_reader.lookup("perro300").unwrap().is_none());
+            }
+            // Small offsets take a byte or two
+            assert!(sizes[1] < sizes[0]);
+        }
+
+        // 32-bit offsets fail instead of wrapping
+        assert!(Offsets::U32.write(&mut vec![], 1 << 32).is_err());
+        assert!(Offsets::Varint.write(&mut vec![], 1 << 32).is_ok());
+    }
+
+    #[test]
+    fn test_bounded_groups() {
+        // All share the same 3 byte prefix, which would be a single group
+        let mut words: Vec<String> = (0..200).map(|i| format!("会社{}", i)).collect();
+        words.extend(["会".to_string(), "apple".to_string(), "会話".to_string()]);
+        let tagged_words = mono_words(&words);
+
+        let grouping = Grouping::Bounded {
+            max_group_size: 256,
+        };
+        // Small frames, so groups and entries straddle frame boundaries
+        let opts = WriterOptions {
+            grouping,
+            codec: Codec::Zstd,
+            compression: Compression {
+                level2: RegionCompression {
+                    level: 3,
+                    frame_size: FrameSize::Uncompressed(500),
+                },
+                data: RegionCompression {
+                    level: 19,
+                    frame_size: FrameSize::Compressed(100),
+                },
+            },
+            offsets: Offsets::U32,
+            layout: StreamLayout::Split,
+            restart_interval: 4,
+            mphf: false,
+            fst: false,
+            bloom_bits_per_key: 0,
+            string_table_min_count: 0,
+            signing_key: None,
+            frequency_list: Vec::new(),
+            hot_words: 0,
+        };
+        let index = encode_index(
+            tagged_words.iter().map(|w| (w.word.as_str(), w)),
+            &opts,
+            &HashSet::new(),
+        )
+        .unwrap();
+        let mut pos = 0;
+        let mut groups = 0;
+        while pos < index.level1_data.len() {
+            let key_end = pos + 1 + index.level1_data[pos] as usize;
+            let size =
+                u32::from_le_bytes(index.level1_data[key_end..key_end + 4].try_into().unwrap());
+            assert!(size <= 256, "group too big: {}", size);
+            pos = key_end + 8;
+            groups += 1;
+        }
+        assert!(groups > 1);
+
+        let mut dict_reader = open_dict(&tagged_words, &[], &opts);
+        assert_eq!(dict_reader.grouping(), grouping);
+        assert_eq!(dict_reader.compression(), Some(opts.compression));
+        for w in &words {
+            let found = dict_reader.lookup(w).unwrap().expect("word missing");
+            assert_eq!(&found.word, w);
+        }
+        for missing in ["0", "b", "会社", "会社999", "会議", "zzz"] {
+            assert!(dict_reader.lookup(missing).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_string_table() {
+        let words: Vec<String> = (0..50).map(|i| format!("gata{}", i)).collect();
+        let test_words: Vec<(&str, &str, &str, bool)> = (words.iter().enumerate())
+            .map(|(i, w)| {
+                let gloss = match i {
+                    0 => "\0literal",
+                    _ => "feminine form of gato",
+                };
+                (w.as_str(), "noun", gloss, true)
+            })
+            .collect();
+        let tagged_words = tagged_words(&test_words);
+
+        let opts = WriterOptions {
+            codec: Codec::Stored,
+            string_table_min_count: 2,
+            ..Default::default()
+        };
+        let mut buffer = Vec::new();
+        let report = write_tagged(&mut buffer, &tagged_words, &[], 0, &opts).unwrap();
+        // A line starting with NUL is just text, stored inline
+        assert_eq!(report.strings, 1);
+        let repeats = buffer
+            .windows(14)
+            .filter(|w| w == b"feminine form ")
+            .count();
+        assert_eq!(repeats, 1);
+
+        let mut dict_reader = DictionaryReader::open(Cursor::new(buffer)).unwrap();
+        assert!(dict_reader.has_string_table());
+        for (i, w) in words.iter().enumerate() {
+            let found = dict_reader.lookup(w).unwrap().expect("word missing");
+            let line = &found.entries[0].senses[0].glosses[0].gloss_lines[0];
+            match i {
+                0 => assert_eq!(line, "\0literal"),
+                _ => assert_eq!(line, "feminine form of gato"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_interleaved_layout() {
+        let words: Vec<String> = (0..300).map(|i| format!("sana{}", i)).collect();
+        let tagged_words = mono_words(&words);
+        let plurals: Vec<(String, &str)> = (words.iter())
+            .map(|w| (format!("{}t", w), w.as_str()))
+            .collect();
+        let forms = plural_forms(&plurals, &tagged_words);
+
+        let region = RegionCompression {
+            level: 3,
+            frame_size: FrameSize::Uncompressed(1000),
+        };
+        let compression = Compression {
+            level2: region,
+            data: region,
+        };
+        for (codec, mphf) in [(Codec::Zstd, false), (Codec::Lz4, true)] {
+            let opts = WriterOptions {
+                codec,
+                compression,
+                layout: StreamLayout::Interleaved,
+                grouping: Grouping::Bounded {
+                    max_group_size: 200,
+                },
+                mphf,
+                ..Default::default()
+            };
+            let mut dict_reader = open_dict(&tagged_words, &forms, &opts);
+            for w in &words {
+                let found = dict_reader.lookup(w).unwrap().expect("word missing");
+                assert_eq!(found.entries[0].senses[0].glosses[0].gloss_lines[0], *w);
+                let by_id = dict_reader.lookup_by_id(found.id.unwrap()).unwrap();
+                assert_eq!(&by_id.unwrap().word, w);
+                assert_eq!(
+                    &dict_reader
+                        .lookup(&format!("{}t", w))
+                        .unwrap()
+                        .unwrap()
+                        .word,
+                    w
+                );
+            }
+            assert!(dict_reader.lookup("sana300").unwrap().is_none());
+        }
+
+        // Regions hold whole groups, and only exceed the frame size when a
+        // single group does
+        let opts = WriterOptions {
+            grouping: Grouping::Bounded {
+                max_group_size: 200,
+            },
+            restart_interval: 16,
+            ..Default::default()
+        };
+        let index = encode_index(
+            tagged_words.iter().map(|w| (w.word.as_str(), w)),
+            &opts,
+            &HashSet::new(),
+        )
+        .unwrap();
+        let mut index = index.map_binary_data(Cursor::new);
+        let mut regions = Vec::new();
+        interleave(&mut index, FrameSize::Uncompressed(1000), |region| {
+            regions.push(region.to_vec());
+            Ok(())
+        })
+        .unwrap();
+        assert!(regions.len() > 1);
+        let group_ends: Vec<usize> = (index.group_ends.iter())
+            .map(|(level2_end, binary_end)| level2_end + binary_end)
+            .collect();
+        let mut region_end = 0;
+        for region in &regions {
+            assert!(region.len() <= 1000);
+            region_end += region.len();
+            assert!(group_ends.contains(&region_end));
+        }
+        assert_eq!(region_end, *group_ends.last().unwrap());
+    }
+
+    #[test]
+    fn test_dictionary_writer() {
+        let tagged_words = mono_words(&["casa", "perro"]);
+
+        let writer = DictionaryWriter::new()
+            .codec(Codec::Lz4)
+            .max_group_size(64)
+            .timestamp(1_700_000_000);
+        let mut buffer = Vec::new();
+        assert_eq!(
+            writer
+                .write(&mut buffer, &tagged_words, &[])
+                .unwrap()
+                .word_count,
+            2
+        );
+        let mut dict_reader = DictionaryReader::open(Cursor::new(buffer)).unwrap();
+        assert_eq!(
+            dict_reader.created_at(),
+            UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000)
+        );
+        assert!(dict_reader.lookup("perro").unwrap().is_some());
+
+        // Errors are typed instead of panics
+        let mut long = tagged_words[0].clone();
+        long.word = "a".repeat(300);
+        let err = writer.write(&mut Vec::new(), [&long], &[]).unwrap_err();
+        assert!(matches!(err, WriterError::InvalidKey(_)), "{}", err);
+        let mut empty = tagged_words[0].clone();
+        empty.word = String::new();
+        let err = writer.write(&mut Vec::new(), [&empty], &[]).unwrap_err();
+        assert!(matches!(err, WriterError::InvalidKey(_)), "{}", err);
+        let mut data = Vec::new();
+        tagged_words[0].serialize(&mut data).unwrap();
+        data.truncate(data.len() / 2);
+        let word = tagged_words[0].word.clone();
+        assert!(WordWithTaggedEntries::named_deserialize(&mut data.as_slice(), word).is_err());
+        let err = aggregate_entries(vec![]).unwrap_err();
+        assert!(matches!(err, WriterError::NoEntries(_)), "{}", err);
+    }
+
+    #[test]
+    fn test_over_255_items() {
+        let mut entry = crate::test_util::test_entry("verb", "to put");
+        let lines = |n: usize| (0..n).map(|i| format!("line {}", i)).collect::<Vec<_>>();
+        // Incompressible lines, so the entry spans several compressed reads
+        let mut x = 1u64;
+        let noise = (0..300)
+            .map(|_| {
+                (0..1000)
+                    .map(|_| {
+                        x ^= x << 13;
+                        x ^= x >> 7;
+                        x ^= x << 17;
+                        (b'a' + (x % 26) as u8) as char
+                    })
+                    .collect::<String>()
+                    .into()
+            })
+            .collect();
+        let sense = entry.senses[0].clone();
+        entry.senses[0].glosses = vec![sense.glosses[0].clone(); 260];
+        entry.senses[0].glosses[0].gloss_lines = noise;
+        entry.senses.extend(vec![sense; 399]);
+        let mut word = WordWithTaggedEntries {
+            tag: WordTag::English,
+            word: "set".to_string(),
+            id: None,
+            entries: vec![entry],
+            sounds: None,
+            hyphenations: lines(256),
+            redirects: lines(1000),
+        };
+
+        let opts = WriterOptions::default();
+        let mut dict_reader = open_dict(std::slice::from_ref(&word), &[], &opts);
+        let found = dict_reader.lookup("set").unwrap().unwrap();
+        assert_eq!(found.entries[0].senses.len(), 400);
+        assert_eq!(found.entries[0].senses[0].glosses.len(), 260);
+        assert_eq!(found.entries[0].senses[0].glosses[0].gloss_lines.len(), 300);
+        assert_eq!(found.hyphenations.len(), 256);
+        assert_eq!(found.redirects[999], "line 999");
+
+        // Fields that still have a 1 byte count report the overflow
+        word.entries = vec![word.entries[0].clone(); 256];
+        let err = write_tagged(&mut Vec::new(), [&word], &[], 0, &opts).unwrap_err();
+        assert!(err.to_string().contains("set"), "{}", err);
+
+        // Forms too, rather than dropping lemmas or tags
+        let words: Vec<String> = (0..256).map(|i| format!("sana{}", i)).collect();
+        let tagged_words = mono_words(&words);
+        let inflected = |form: &str, lemma: &str, tags: usize| InflectedForm {
+            form: form.to_string(),
+            lemma: lemma.to_string(),
+            tags: lines(tags),
+        };
+        for forms in [
+            (words.iter()).map(|w| inflected("sanat", w, 1)).collect(),
+            vec![inflected("sanat", "sana0", 256)],
+        ] {
+            let forms = build_forms_index(forms, &tagged_words);
+            let err = write_tagged(&mut Vec::new(), &tagged_words, &forms, 0, &opts).unwrap_err();
+            assert!(
+                matches!(&err, WriterError::Entry { key, .. } if key == "sanat"),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_merge_same_pos_senses() {
+        let mut entry = WordEntryComplete {
+            senses: vec![
+                crate::Sense {
+                    pos: PartOfSpeech::Noun,
+                    links: vec![],
+                    glosses: vec![crate::Gloss {
+                        gloss_lines: vec!["first noun definition".into()],
+                    }],
+                },
+                crate::Sense {
+                    pos: PartOfSpeech::Adj,
+                    links: vec![],
+                    glosses: vec![crate::Gloss {
+                        gloss_lines: vec!["adjective definition".into()],
+                    }],
+                },
+                crate::Sense {
+                    pos: PartOfSpeech::Noun,
+                    links: vec![],
+                    glosses: vec![crate::Gloss {
+                        gloss_lines: vec!["second noun definition".into()],
+                    }],
+                },
+            ],
+        };
+
+        merge_same_pos_senses(&mut entry.senses);
+
+        // Should now have only 2 senses: 1 adj and 1 noun (with 2 glosses)
+        assert_eq!(entry.senses.len(), 2);
+
+        // Find the noun sense (should be first due to sorting)
+        let noun_sense = entry
+            .senses
+            .iter()
+            .find(|s| s.pos == PartOfSpeech::Noun)
+            .unwrap();
+        assert_eq!(noun_sense.glosses.len(), 2);
+
+        let adj_sense = entry
+            .senses
+            .iter()
+            .find(|s| s.pos == PartOfSpeech::Adj)
+            .unwrap();
+        assert_eq!(adj_sense.glosses.len(), 1);
+
+        // Sorted by POS name, glosses in input order
+        let pos: Vec<PartOfSpeech> = entry.senses.iter().map(|s| s.pos).collect();
+        assert_eq!(pos, [PartOfSpeech::Adj, PartOfSpeech::Noun]);
+        assert_eq!(
+            noun_sense.glosses[1].gloss_lines[0],
+            "second noun definition"
+        );
+    }
+
+    #[test]
+    fn test_reproducible_builds() {
+        let test_words = vec![
+            ("perro", "noun", "dog", true),
+            ("casa", "verb", "to marry", true),
+            ("casa", "noun", "house", true),
+            ("casa", "verb", "to match", true),
+            ("gato", "noun", "cat", true),
+            ("perro", "adj", "lousy", true),
+        ];
+        let opts = WriterOptions {
+            mphf: true,
+            bloom_bits_per_key: 10,
+            string_table_min_count: 1,
+            frequency_list: vec!["gato".to_string()],
+            hot_words: 1,
+            ..Default::default()
+        };
+        let build = |test_words: &[(&str, &str, &str, bool)]| {
+            write_dict(&tagged_words(test_words), &[], &opts)
+        };
+
+        // Each build hashes with new random keys; only the order of a
+        // word's own entries matters, as it's the order of its glosses
+        let first = build(&test_words);
+        assert!(first == build(&test_words));
+        let mut other_words_first = test_words;
+        other_words_first.sort_by_key(|(w, ..)| *w != "gato");
+        assert!(first == build(&other_words_first));
+
+        let mut dict_reader = DictionaryReader::open(Cursor::new(first)).unwrap();
+        let casa = dict_reader.lookup("casa").unwrap().unwrap();
+        let glosses: Vec<&str> = (casa.entries[0].senses.iter())
+            .flat_map(|s| &s.glosses)
+            .map(|g| g.gloss_lines[0].text().unwrap())
+            .collect();
+        assert_eq!(glosses, ["house", "to marry", "to match"]);
+
+        assert_eq!(
+            parse_source_date_epoch("1700000000\n").unwrap(),
+            1_700_000_000
+        );
+        assert!(parse_source_date_epoch("yesterday").is_err());
+        assert!(parse_source_date_epoch("-1").is_err());
+        // As DictionaryWriter::new() leaves it with an invalid value
+        let writer = DictionaryWriter {
+            opts: WriterOptions::default(),
+            timestamp_s: Err(parse_source_date_epoch("yesterday")
+                .unwrap_err()
+                .to_string()),
+        };
+        let words = mono_words(&["casa"]);
+        assert!(matches!(
+            writer.write(Vec::new(), &words, &[]),
+            Err(WriterError::InvalidTimestamp(_))
+        ));
+        assert!(writer.timestamp(0).write(Vec::new(), &words, &[]).is_ok());
+    }
+
+    #[test]
+    fn test_build_report() {
+        let words: Vec<String> = (0..100).map(|i| format!("talo{}", i)).collect();
+        let test_words: Vec<(&str, &str, &str, bool)> = (words.iter())
+            .map(|w| (w.as_str(), "noun", "house", true))
+            .collect();
+        let tagged_words = tagged_words(&test_words);
+        let forms = plural_forms(&[("talo7t", "talo7")], &tagged_words);
+
+        for layout in [StreamLayout::Split, StreamLayout::Interleaved] {
+            let opts = WriterOptions {
+                layout,
+                string_table_min_count: 2,
+                frequency_list: vec!["talo3".to_string(), "talo50".to_string()],
+                hot_words: 2,
+                ..Default::default()
+            };
+            let mut buffer = Vec::new();
+            let report = write_tagged(&mut buffer, &tagged_words, &forms, 0, &opts).unwrap();
+            assert_eq!(report.word_count, 100);
+            assert_eq!(report.form_count, 1);
+            assert_eq!(report.file_size, buffer.len() as u64);
+            assert!(report.stream_size < report.file_size);
+            assert!(report.level1_size > 0 && report.level2_size > 0);
+            assert!(report.compressed_data_size > 0);
+            // Each key after the first of its group shares at least "talo"
+            assert!(report.prefix_savings >= 4 * 90);
+            let buckets = report.entry_sizes;
+            assert_eq!(buckets.under_128b + buckets.under_16kib, 100);
+            assert_eq!(buckets.under_2mib + buckets.larger, 0);
+            assert_eq!(report.hot_words, 2);
+            assert_eq!(report.strings, 1);
+        }
+    }
+}